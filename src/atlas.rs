@@ -0,0 +1,50 @@
+//! Texture sheet holding the tiles blocks are drawn with
+
+use anyhow::{Context, Result};
+use macroquad::prelude::{Image, ImageFormat, Rect};
+use std::{fs, path::Path};
+
+/// Texture sheet loaded at startup
+pub const TEXTURE_SHEET: &str = "assets/texture_sheet.png";
+/// Width and height of a tile in pixels
+pub const TILE_SIZE: u16 = 16;
+
+/// Layout of the tiles within a texture sheet
+///
+/// Tiles are numbered left to right then top to bottom, starting at 0 in the
+/// top left corner. This is the `texture` index of a block definition.
+pub struct Atlas {
+    columns: u16,
+    rows: u16,
+}
+impl Atlas {
+    /// Layout of a sheet of the given size in pixels
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            columns: width / TILE_SIZE,
+            rows: height / TILE_SIZE,
+        }
+    }
+    /// Reads a texture sheet from disk, returns its layout and pixels
+    pub fn load(path: impl AsRef<Path>) -> Result<(Self, Image)> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).with_context(|| format!("could not read {}", path.display()))?;
+        let image = Image::from_file_with_format(&bytes, Some(ImageFormat::Png))
+            .with_context(|| format!("could not decode {}", path.display()))?;
+        Ok((Self::new(image.width, image.height), image))
+    }
+    /// Returns the normalized texture cords of a tile, none if the sheet has no such tile
+    pub fn uv(&self, tile: u16) -> Option<Rect> {
+        if tile >= self.columns * self.rows {
+            return None;
+        }
+        let w = 1. / self.columns as f32;
+        let h = 1. / self.rows as f32;
+        Some(Rect {
+            x: (tile % self.columns) as f32 * w,
+            y: (tile / self.columns) as f32 * h,
+            w,
+            h,
+        })
+    }
+}
@@ -1,15 +1,19 @@
 //! Defines a camera to view the world
 
-use super::ChunkMesh;
-use crate::{conversion, WorldManager};
+use super::{Atlas, ChunkMesh, Quad, TEXTURE_SHEET};
+use crate::{conversion, WorldManager, WorldRect};
 use macroquad::{
+    math::{vec3, Mat4},
+    models::{draw_mesh, Mesh},
     prelude::{
-        draw_rectangle, draw_rectangle_lines, draw_text, get_fps, gl_use_default_material,
-        gl_use_material, load_material, mouse_position, Color, Material, MaterialParams,
-        ShaderSource, UniformType, BLACK, BLUE, PINK, RED, WHITE,
+        draw_rectangle, draw_rectangle_lines, draw_text, draw_texture_ex, get_fps,
+        gl_use_default_material, gl_use_material, load_material, mouse_position, vec2, Color,
+        DrawTextureParams, FilterMode, Material, MaterialParams, ShaderSource, Texture2D,
+        UniformType, BLACK, BLUE, PINK, RED, WHITE,
     },
-    window,
+    window::{self, get_internal_gl},
 };
+use std::{collections::HashMap, mem};
 
 /// Module which contains camera configuration flags
 pub mod flags {
@@ -18,15 +22,43 @@ pub mod flags {
     pub const DRAW_SELECTION_BOX: u8 = 1 << 2;
     pub const DEBUG_MENU: u8 = 1 << 3;
     pub const CLAMP_ZOOM: u8 = 1 << 4;
+    pub const DEBUG_BIOMES: u8 = 1 << 5;
 }
 
 const DEBUG_CHUNK_COLOR: Color = BLUE;
 const DEBUG_QUAD_COLOR: Color = RED;
 const SELECT_BOX_COLOR: Color = PINK;
 const DEBUG_LINE_WIDTH: f32 = 2.0;
+const DEBUG_BIOME_ALPHA: f32 = 0.4;
+/// Below this zoom chunks are drawn from a cached texture instead of their mesh
+const TEXTURE_ZOOM: f32 = 4.0;
 
 type Flags = u8;
 
+/// Graphics of a chunk kept between frames
+enum ChunkGraphics {
+    /// Flat colored and textured triangles
+    Mesh(Mesh, Mesh),
+    /// One pixel per block, used when zoomed out far enough that meshes are wasted
+    Texture(Texture2D),
+}
+impl ChunkGraphics {
+    fn build(chunk_mesh: &ChunkMesh, zoom: f32, atlas: Option<&(Atlas, Texture2D)>) -> Self {
+        if zoom < TEXTURE_ZOOM {
+            let texture = Texture2D::from_rgba8(16, 16, &chunk_mesh.rasterize());
+            texture.set_filter(FilterMode::Nearest);
+            ChunkGraphics::Texture(texture)
+        } else {
+            let (flat, mut textured) = chunk_mesh.to_meshes(atlas.map(|(atlas, _)| atlas));
+            textured.texture = atlas.map(|(_, texture)| texture.clone());
+            ChunkGraphics::Mesh(flat, textured)
+        }
+    }
+    fn suits(&self, zoom: f32) -> bool {
+        matches!(self, ChunkGraphics::Texture(_)) == (zoom < TEXTURE_ZOOM)
+    }
+}
+
 pub struct Camera {
     x: i64,
     y: i64,
@@ -34,6 +66,10 @@ pub struct Camera {
     flags: Flags,
     bg_mat: Material,
     chunks_drawn: usize,
+    /// Graphics of every chunk drawn last frame with the id of the [`ChunkMesh`] they were built from
+    chunk_cache: HashMap<(i64, i64), (u64, ChunkGraphics)>,
+    /// Tiles blocks are drawn with, blocks fall back to their color without it
+    atlas: Option<(Atlas, Texture2D)>,
 }
 
 impl Camera {
@@ -49,6 +85,17 @@ impl Camera {
             },
         )
         .expect("Error loading shaders");
+        let atlas = match Atlas::load(TEXTURE_SHEET) {
+            Ok((atlas, image)) => {
+                let texture = Texture2D::from_image(&image);
+                texture.set_filter(FilterMode::Nearest);
+                Some((atlas, texture))
+            }
+            Err(e) => {
+                eprintln!("{e:#}, drawing blocks with flat colors");
+                None
+            }
+        };
         Camera {
             zoom: 30.0,
             x: 0,
@@ -56,6 +103,8 @@ impl Camera {
             bg_mat: material,
             flags,
             chunks_drawn: 0,
+            chunk_cache: HashMap::new(),
+            atlas,
         }
     }
 
@@ -72,35 +121,56 @@ impl Camera {
         self.flags = flags;
     }
 
-    pub fn draw(&mut self, manager: &mut WorldManager) {
-        self.draw_background();
+    /// Turns the given flags on if they are off and off if they are on
+    pub fn toggle_flags(&mut self, flags: Flags) {
+        self.flags ^= flags;
+    }
 
-        let chunk_dim = self.zoom * 16.0;
-        self.chunks_drawn = 0;
+    pub fn get_zoom(&self) -> f32 {
+        self.zoom
+    }
 
-        let screen_width_chunks = (window::screen_width() / chunk_dim) as isize;
-        let screen_height_chunks = (window::screen_height() / chunk_dim) as isize;
+    /// Draws every chunk in view whose mesh is ready, the rest are skipped
+    /// until [`WorldManager::update_view`] has prepared them
+    pub fn draw(&mut self, manager: &WorldManager) {
+        self.draw_background();
 
-        for x in -1..=screen_width_chunks {
-            for y in -1..=screen_height_chunks {
-                let screen_x = x as f32 * chunk_dim;
-                let screen_y = y as f32 * chunk_dim;
-                let (world_x, world_y) = self.screen_to_world_cord(screen_x, screen_y);
-                let (region_x, region_y) = conversion::get_region_cords(&world_x, &world_y);
-                let (chunk_region_x, chunk_region_y) =
-                    conversion::get_region_chunk_cords(&world_x, &world_y);
-                let (chunk_cord_x, chunk_cord_y) =
-                    conversion::get_chunk_world_cords(&world_x, &world_y);
+        self.chunks_drawn = 0;
+        // Chunks that are not drawn this frame drop out of the cache
+        let mut previous_cache = mem::take(&mut self.chunk_cache);
 
+        let area = self.visible_area();
+        let (min_x, min_y) = conversion::get_chunk_world_cords(&area.min_x, &area.min_y);
+        for chunk_cord_y in (min_y..=area.max_y).step_by(16) {
+            for chunk_cord_x in (min_x..=area.max_x).step_by(16) {
                 let rel_world_x = (self.x - chunk_cord_x) as f32;
                 let rel_world_y = (self.y - chunk_cord_y) as f32;
 
-                self.draw_chunk_mesh(
-                    manager.get_chunk_mesh(&region_x, &region_y, &chunk_region_x, &chunk_region_y),
-                    rel_world_x,
-                    rel_world_y,
-                );
-                self.chunks_drawn += 1;
+                let key = (chunk_cord_x, chunk_cord_y);
+                if let Some(chunk_mesh) = manager.chunk_mesh(&chunk_cord_x, &chunk_cord_y) {
+                    let cached = previous_cache
+                        .remove(&key)
+                        .filter(|(id, graphics)| id == &chunk_mesh.id && graphics.suits(self.zoom))
+                        .unwrap_or_else(|| {
+                            (
+                                chunk_mesh.id,
+                                ChunkGraphics::build(chunk_mesh, self.zoom, self.atlas.as_ref()),
+                            )
+                        });
+                    self.draw_chunk_mesh(chunk_mesh, &cached.1, rel_world_x, rel_world_y);
+                    self.chunk_cache.insert(key, cached);
+                    self.chunks_drawn += 1;
+                }
+
+                if self.flags & flags::DEBUG_BIOMES > 0 {
+                    if let Some(biome) = manager.get_biome(&(chunk_cord_x + 8)) {
+                        self.draw_chunk_overlay(
+                            biome.params().debug_color,
+                            rel_world_x,
+                            rel_world_y,
+                        );
+                    }
+                }
             }
         }
 
@@ -113,39 +183,75 @@ impl Camera {
         }
     }
 
-    fn screen_to_world_cord(&self, screen_x: f32, screen_y: f32) -> (i64, i64) {
+    /// Returns the world cords of every block that is at least partially on screen
+    pub fn visible_area(&self) -> WorldRect {
+        let (x1, y1) = self.screen_to_world_cord(0.0, 0.0);
+        let (x2, y2) = self.screen_to_world_cord(window::screen_width(), window::screen_height());
+        WorldRect::new(x1, y1, x2, y2)
+    }
+
+    /// Returns the block under a point on screen
+    ///
+    /// World x and y grow towards the left and the top of the screen
+    pub fn screen_to_world_cord(&self, screen_x: f32, screen_y: f32) -> (i64, i64) {
         let center_x = window::screen_width() / 2.0;
         let center_y = window::screen_height() / 2.0;
         (
-            self.x - ((screen_x - center_x) / self.zoom) as i64,
-            self.y - ((screen_y - center_y) / self.zoom) as i64,
+            self.x - ((screen_x - center_x) / self.zoom).floor() as i64,
+            self.y - ((screen_y - center_y) / self.zoom).floor() as i64,
         )
     }
 
-    fn draw_chunk_mesh(&self, chunk_mesh: &ChunkMesh, rel_world_x: f32, rel_world_y: f32) {
+    /// Returns the top left corner of a block on screen, the inverse of [`Self::screen_to_world_cord`]
+    pub fn world_to_screen_cord(&self, world_x: &i64, world_y: &i64) -> (f32, f32) {
         let center_x = window::screen_width() / 2.0;
         let center_y = window::screen_height() / 2.0;
+        (
+            (self.x - world_x) as f32 * self.zoom + center_x,
+            (self.y - world_y) as f32 * self.zoom + center_y,
+        )
+    }
 
-        for (color, rect) in chunk_mesh.mesh.iter() {
-            // Flip x and y coordinates within the chunk
-            let flipped_x = 16.0 - rect.x - rect.w;
-            let flipped_y = 16.0 - rect.y - rect.h;
-
-            let screen_x = (rel_world_x + flipped_x) * self.zoom + center_x;
-            let screen_y = (rel_world_y + flipped_y) * self.zoom + center_y;
+    fn draw_chunk_mesh(
+        &self,
+        chunk_mesh: &ChunkMesh,
+        graphics: &ChunkGraphics,
+        rel_world_x: f32,
+        rel_world_y: f32,
+    ) {
+        let center_x = window::screen_width() / 2.0;
+        let center_y = window::screen_height() / 2.0;
+        let left = (rel_world_x - 15.0) * self.zoom + center_x;
+        let top = (rel_world_y - 15.0) * self.zoom + center_y;
 
-            draw_rectangle(
-                screen_x,
-                screen_y,
-                rect.w * self.zoom,
-                rect.h * self.zoom,
-                *color,
-            );
+        match graphics {
+            ChunkGraphics::Mesh(flat, textured) => {
+                let transform = Mat4::from_translation(vec3(left, top, 0.0))
+                    * Mat4::from_scale(vec3(self.zoom, self.zoom, 1.0));
+                // SAFETY: only the model stack is touched and it is restored right after drawing
+                let gl = unsafe { get_internal_gl() }.quad_gl;
+                gl.push_model_matrix(transform);
+                draw_mesh(flat);
+                draw_mesh(textured);
+                gl.pop_model_matrix();
+            }
+            ChunkGraphics::Texture(texture) => draw_texture_ex(
+                texture,
+                left,
+                top,
+                WHITE,
+                DrawTextureParams {
+                    dest_size: Some(vec2(16.0 * self.zoom, 16.0 * self.zoom)),
+                    ..Default::default()
+                },
+            ),
+        }
 
-            if self.flags & flags::DEBUG_QUADS > 0 {
+        if self.flags & flags::DEBUG_QUADS > 0 {
+            for Quad { rect, .. } in chunk_mesh.mesh.iter() {
                 draw_rectangle_lines(
-                    screen_x,
-                    screen_y,
+                    left + (16.0 - rect.x - rect.w) * self.zoom,
+                    top + (16.0 - rect.y - rect.h) * self.zoom,
                     rect.w * self.zoom,
                     rect.h * self.zoom,
                     DEBUG_LINE_WIDTH,
@@ -156,8 +262,8 @@ impl Camera {
 
         if self.flags & flags::DEBUG_CHUNKS > 0 {
             draw_rectangle_lines(
-                rel_world_x * self.zoom + center_x,
-                rel_world_y * self.zoom + center_y,
+                left,
+                top,
                 16.0 * self.zoom,
                 16.0 * self.zoom,
                 DEBUG_LINE_WIDTH,
@@ -166,12 +272,24 @@ impl Camera {
         }
     }
 
+    /// Tints a whole chunk, used by debug views
+    fn draw_chunk_overlay(&self, mut color: Color, rel_world_x: f32, rel_world_y: f32) {
+        let center_x = window::screen_width() / 2.0;
+        let center_y = window::screen_height() / 2.0;
+        color.a = DEBUG_BIOME_ALPHA;
+        draw_rectangle(
+            (rel_world_x - 15.0) * self.zoom + center_x,
+            (rel_world_y - 15.0) * self.zoom + center_y,
+            16.0 * self.zoom,
+            16.0 * self.zoom,
+            color,
+        );
+    }
+
     fn draw_selected_block(&self) {
-        let mouse_pos = mouse_position();
-        let world_x = ((mouse_pos.0 - window::screen_width() / 2.0) / self.zoom).floor();
-        let world_y = ((mouse_pos.1 - window::screen_height() / 2.0) / self.zoom).floor();
-        let screen_x = world_x * self.zoom + window::screen_width() / 2.0;
-        let screen_y = world_y * self.zoom + window::screen_height() / 2.0;
+        let (mouse_x, mouse_y) = mouse_position();
+        let (world_x, world_y) = self.screen_to_world_cord(mouse_x, mouse_y);
+        let (screen_x, screen_y) = self.world_to_screen_cord(&world_x, &world_y);
 
         draw_rectangle_lines(
             screen_x,
@@ -197,23 +315,42 @@ impl Camera {
         gl_use_default_material();
     }
 
-    fn draw_debug_menu(&self, manager: &mut WorldManager) {
+    fn draw_debug_menu(&self, manager: &WorldManager) {
+        const MIB: f32 = (1 << 20) as f32;
         let (mouse_x, mouse_y) = mouse_position();
         let (cursor_x, cursor_y) = self.screen_to_world_cord(mouse_x, mouse_y);
         let regions = manager.get_region_count();
-        let block = manager.get_block(&(cursor_x + 15), &(cursor_y + 15));
+        let region_stats = manager.region_stats();
+        let pending_jobs = manager.get_pending_job_count();
+        let active_chunks = manager.get_active_chunk_count();
+        let biome = manager.get_biome(&cursor_x);
+        let mesher = manager.mesh_strategy();
+        let light = manager.peek_light(&cursor_x, &cursor_y);
+        let block = manager.peek_block(&cursor_x, &cursor_y);
 
         let text = format!(
-            "FPS: {}\nRegions Loaded: {}\nChunks Drawn: {}\nZoom Level: {}\nCamera X: {}\nCamera Y: {}\nCursor X: {}\nCursor Y: {}\nBlock: {:?}",
+            "FPS: {}\nRegions Loaded: {}\nRegion Memory: {:.1}/{:.1} MiB\nRegion Cache: {} hits {} misses {} evictions\nChunks Unloaded: {}\nRegion IO Pending: {}\nActive Chunks: {}\nPending Jobs: {}\nChunks Drawn: {}\nMesher: {:?}\nZoom Level: {}\nCamera X: {}\nCamera Y: {}\nCursor X: {}\nCursor Y: {}\nBiome: {:?}\nBlock: {:?}\nLight: {:?}",
             get_fps(),
             regions,
+            region_stats.memory as f32 / MIB,
+            region_stats.budget as f32 / MIB,
+            region_stats.hits,
+            region_stats.misses,
+            region_stats.evictions,
+            region_stats.chunk_unloads,
+            region_stats.pending_io,
+            active_chunks,
+            pending_jobs,
             self.chunks_drawn,
+            mesher,
             self.zoom,
             self.x,
             self.y,
             cursor_x,
             cursor_y,
-            block
+            biome,
+            block,
+            light
         );
 
         const PADDING: f32 = 5.0;
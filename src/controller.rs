@@ -1,6 +1,6 @@
-use super::Camera;
-use crate::WorldManager;
-use macroquad::prelude::{is_key_down, KeyCode};
+use super::{flags, Camera};
+use crate::{MeshType, WorldManager};
+use macroquad::prelude::{is_key_down, is_key_pressed, KeyCode};
 
 /* Camera Options */
 const MAX_ZOOM: f32 = 30.;
@@ -26,7 +26,7 @@ impl InspectController {
     }
 }
 impl Controller for InspectController {
-    fn update(&mut self, camera: &mut Camera, _manager: &mut WorldManager) {
+    fn update(&mut self, camera: &mut Camera, manager: &mut WorldManager) {
         let mut move_speed = 1;
         if is_key_down(KeyCode::LeftShift) {
             move_speed = 5;
@@ -49,6 +49,18 @@ impl Controller for InspectController {
         if is_key_down(KeyCode::X) {
             self.zoom *= 1.1;
         }
+        if is_key_pressed(KeyCode::B) {
+            camera.toggle_flags(flags::DEBUG_BIOMES);
+        }
+        if is_key_pressed(KeyCode::M) {
+            manager.set_mesh_strategy(match manager.mesh_strategy() {
+                MeshType::CULLED => MeshType::GREEDY,
+                MeshType::GREEDY => MeshType::CULLED,
+            });
+        }
+        if is_key_pressed(KeyCode::O) {
+            manager.set_shading(!manager.shading());
+        }
         self.zoom = self.zoom.clamp(MIN_ZOOM, MAX_ZOOM);
         camera.set_pos(self.x, self.y);
         camera.set_zoom(self.zoom);
@@ -105,21 +117,19 @@ impl Controller for PlayerController {
             self.vy -= 0.1;
         }
 
-        if is_key_down(KeyCode::A) {
-            if !manager
+        if is_key_down(KeyCode::A)
+            && !manager
                 .get_block(&(self.x.large + 1), &self.y.large)
                 .is_solid()
-            {
-                self.x.move_by(0.5);
-            }
+        {
+            self.x.move_by(0.5);
         }
-        if is_key_down(KeyCode::D) {
-            if !manager
+        if is_key_down(KeyCode::D)
+            && !manager
                 .get_block(&(self.x.large - 1), &self.y.large)
                 .is_solid()
-            {
-                self.x.move_by(-0.5);
-            }
+        {
+            self.x.move_by(-0.5);
         }
         if is_key_down(KeyCode::Z) {
             self.zoom *= 0.9;
@@ -0,0 +1,118 @@
+//! Mouse driven editing of the world
+
+use super::{Camera, Controller};
+use crate::{blocks::Block, registry::registry, WorldManager};
+use macroquad::prelude::{
+    draw_circle_lines, draw_rectangle, draw_rectangle_lines, draw_text, is_key_pressed,
+    is_mouse_button_down, mouse_position, mouse_wheel, screen_height, Color, KeyCode, MouseButton,
+    BLACK, PINK, WHITE,
+};
+
+/* Brush Options */
+const MAX_BRUSH_RADIUS: u32 = 16;
+const BRUSH_COLOR: Color = PINK;
+
+/* Hotbar Options */
+const HOTBAR_SLOT_SIZE: f32 = 40.0;
+const HOTBAR_PADDING: f32 = 5.0;
+const HOTBAR_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+/// Removes blocks with the left mouse button and places the selected material with the right
+///
+/// Number keys pick a material from the hotbar and the mouse wheel resizes the brush
+pub struct BlockEditor {
+    hotbar: Vec<Block>,
+    selected: usize,
+    brush_radius: u32,
+}
+impl BlockEditor {
+    /// Creates an editor whose hotbar holds the first placeable blocks of the registry
+    pub fn new() -> Self {
+        let hotbar = registry()
+            .blocks()
+            .map(|definition| Block(definition.id))
+            .filter(|block| block != &Block::AIR && block != &Block::WATER_EDGE)
+            .take(HOTBAR_KEYS.len())
+            .collect();
+        Self::with_hotbar(hotbar)
+    }
+    pub fn with_hotbar(hotbar: Vec<Block>) -> Self {
+        Self {
+            hotbar,
+            selected: 0,
+            brush_radius: 0,
+        }
+    }
+    /// Returns the material placed with the right mouse button
+    pub fn selected_block(&self) -> Option<Block> {
+        self.hotbar.get(self.selected).copied()
+    }
+    /// Draws the hotbar and the outline of the brush under the cursor
+    pub fn draw(&self, camera: &Camera) {
+        let (mouse_x, mouse_y) = mouse_position();
+        let (world_x, world_y) = camera.screen_to_world_cord(mouse_x, mouse_y);
+        let (screen_x, screen_y) = camera.world_to_screen_cord(&world_x, &world_y);
+        let zoom = camera.get_zoom();
+        if self.brush_radius > 0 {
+            draw_circle_lines(
+                screen_x + zoom / 2.0,
+                screen_y + zoom / 2.0,
+                (self.brush_radius as f32 + 0.5) * zoom,
+                2.0,
+                BRUSH_COLOR,
+            );
+        }
+
+        let top = screen_height() - HOTBAR_SLOT_SIZE - HOTBAR_PADDING;
+        for (i, block) in self.hotbar.iter().enumerate() {
+            let left = HOTBAR_PADDING + i as f32 * (HOTBAR_SLOT_SIZE + HOTBAR_PADDING);
+            draw_rectangle(left, top, HOTBAR_SLOT_SIZE, HOTBAR_SLOT_SIZE, block.color());
+            let outline = if i == self.selected { WHITE } else { BLACK };
+            draw_rectangle_lines(left, top, HOTBAR_SLOT_SIZE, HOTBAR_SLOT_SIZE, 3.0, outline);
+            draw_text(&(i + 1).to_string(), left + 4.0, top + 16.0, 20.0, WHITE);
+        }
+    }
+}
+impl Default for BlockEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Controller for BlockEditor {
+    fn update(&mut self, camera: &mut Camera, manager: &mut WorldManager) {
+        for (i, key) in HOTBAR_KEYS.iter().enumerate().take(self.hotbar.len()) {
+            if is_key_pressed(*key) {
+                self.selected = i;
+            }
+        }
+
+        let scroll = mouse_wheel().1;
+        if scroll > 0.0 {
+            self.brush_radius = (self.brush_radius + 1).min(MAX_BRUSH_RADIUS);
+        } else if scroll < 0.0 {
+            self.brush_radius = self.brush_radius.saturating_sub(1);
+        }
+
+        // Same mapping the selection box is drawn with so both always line up
+        let (mouse_x, mouse_y) = mouse_position();
+        let (world_x, world_y) = camera.screen_to_world_cord(mouse_x, mouse_y);
+
+        if is_mouse_button_down(MouseButton::Left) {
+            manager.fill_circle(&world_x, &world_y, self.brush_radius, Block::AIR);
+        } else if is_mouse_button_down(MouseButton::Right) {
+            if let Some(block) = self.selected_block() {
+                manager.fill_circle(&world_x, &world_y, self.brush_radius, block);
+            }
+        }
+    }
+}
@@ -4,6 +4,12 @@ pub use world::*;
 mod mesh;
 pub use mesh::*;
 
+mod atlas;
+pub use atlas::*;
+
+mod light;
+pub use light::*;
+
 mod camera;
 pub use camera::*;
 
@@ -12,3 +18,6 @@ pub use world_manager::*;
 
 mod controller;
 pub use controller::*;
+
+mod editor;
+pub use editor::*;
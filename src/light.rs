@@ -0,0 +1,92 @@
+//! Light levels of blocks from the sky and from glowing blocks
+
+use super::ChunkWindow;
+use std::collections::VecDeque;
+
+/// Brightest light level, light fades by one level per block so this is also how far it reaches
+pub const MAX_LIGHT: u8 = 8;
+/// Brightness of blocks no light reaches
+const MIN_BRIGHTNESS: f32 = 0.15;
+
+/// Light level of every block within a chunk
+#[derive(Clone)]
+pub struct LightMap {
+    levels: [u8; 16 * 16],
+}
+impl LightMap {
+    /// Lights a chunk from the blocks around it
+    ///
+    /// Non solid blocks above the generated surface get full sunlight unless a
+    /// solid block sits above them, within the window or in a column the window
+    /// marks as roofed. Glowing blocks give off their own light. Light spreads
+    /// through non solid blocks, fading by one level per block, and lights up
+    /// the face of any solid block it reaches.
+    /// Since light fades out within [`ChunkMesh::MARGIN`](crate::ChunkMesh::MARGIN)
+    /// blocks the window holds every source that can reach the chunk.
+    pub fn compute(window: &ChunkWindow) -> Self {
+        let size = ChunkWindow::SIZE;
+        let margin = (size as i64 - 16) / 2;
+        let cord = |i: usize| ((i % size) as i64 - margin, (i / size) as i64 - margin);
+        let passes = |i: usize| {
+            let (x, y) = cord(i);
+            let block = window.get(x, y);
+            !block.is_solid() || block.light() > 0
+        };
+
+        let mut levels: Vec<u8> = (0..size * size)
+            .map(|i| {
+                let (x, y) = cord(i);
+                window.get(x, y).light().min(MAX_LIGHT)
+            })
+            .collect();
+        for x in (-margin..16 + margin).filter(|x| !window.roofed(*x)) {
+            let surface = window.surface(x);
+            for y in (-margin..16 + margin).rev() {
+                if window.get(x, y).is_solid() || surface.is_some_and(|surface| y <= surface) {
+                    break;
+                }
+                levels[(x + margin) as usize + (y + margin) as usize * size] = MAX_LIGHT;
+            }
+        }
+
+        let mut queue: VecDeque<usize> = (0..levels.len())
+            .filter(|i| levels[*i] > 0 && passes(*i))
+            .collect();
+        while let Some(i) = queue.pop_front() {
+            let (x, y) = (i % size, i / size);
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < size).then(|| i + 1),
+                (y > 0).then(|| i - size),
+                (y + 1 < size).then(|| i + size),
+            ];
+            for neighbour in neighbours.into_iter().flatten() {
+                if passes(neighbour) {
+                    let level = levels[i].saturating_sub(1);
+                    if level > levels[neighbour] {
+                        levels[neighbour] = level;
+                        queue.push_back(neighbour);
+                    }
+                } else {
+                    // Solid blocks are lit on their face but do not pass light on
+                    levels[neighbour] = levels[neighbour].max(levels[i]);
+                }
+            }
+        }
+
+        let levels = std::array::from_fn(|i| {
+            let x = i % 16 + margin as usize;
+            let y = i / 16 + margin as usize;
+            levels[x + y * size]
+        });
+        Self { levels }
+    }
+    /// Returns the light level of a block by chunk local cords
+    pub fn get(&self, x: &u8, y: &u8) -> u8 {
+        self.levels[(x + y * 16) as usize]
+    }
+    /// How bright a block at an index into the chunk is drawn
+    pub fn brightness(&self, index: usize) -> f32 {
+        MIN_BRIGHTNESS + (1. - MIN_BRIGHTNESS) * self.levels[index] as f32 / MAX_LIGHT as f32
+    }
+}
@@ -1,21 +1,73 @@
-use macroquad::prelude::next_frame;
+use macroquad::prelude::{is_quit_requested, next_frame, prevent_quit};
 
 use rand::{self, Rng};
 use sand_engine::*;
 
 #[macroquad::main("Sand Engine")]
 async fn main() {
-    let seed = rand::thread_rng().gen_range(0..u32::MAX);
+    // Block definitions can be edited without recompiling
+    match registry::BlockRegistry::load("assets/blocks.json") {
+        Ok(blocks) => blocks
+            .install()
+            .expect("Block registry was used before loading"),
+        Err(e) => eprintln!("{e:#}, falling back to builtin blocks"),
+    }
+
+    // Usage: sand-engine [world name] [--flat] [--region-budget=<MiB>]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let name = args
+        .iter()
+        .find(|arg| !arg.starts_with("--"))
+        .map_or("My World", String::as_str);
+
+    // Only used when the world does not exist yet
+    let new_meta = || {
+        let seed = rand::thread_rng().gen_range(0..u32::MAX);
+        let mut meta = save::WorldMeta::new(name, seed);
+        meta.generator = if args.iter().any(|arg| arg == "--flat") {
+            gen::GeneratorKind::flat()
+        } else {
+            gen::GeneratorKind::with_structures(gen::features::STRUCTURES_DIR)?
+        };
+        Ok(meta)
+    };
+    let world = save::WorldSave::open_or_create(save::WorldSave::path_for(name), new_meta)
+        .expect("Could not open world");
 
     let mut camera = Camera::new(
-        flags::DEBUG_MENU | flags::DRAW_SELECTION_BOX | flags::CLAMP_ZOOM | flags::DEBUG_CHUNKS |flags::DEBUG_QUADS,
+        flags::DEBUG_MENU
+            | flags::DRAW_SELECTION_BOX
+            | flags::CLAMP_ZOOM
+            | flags::DEBUG_CHUNKS
+            | flags::DEBUG_QUADS,
     );
-    let mut manager = WorldManager::new(seed);
+    let mut manager = WorldManager::new(world).expect("Could not open world");
+    if let Some(budget) = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--region-budget="))
+    {
+        let mib: usize = budget
+            .parse()
+            .expect("--region-budget takes a number of MiB");
+        manager.set_region_budget(mib << 20);
+    }
     let mut controller = InspectController::new(0, 0);
-    loop {
-        camera.draw(&mut manager);
+    let mut editor = BlockEditor::new();
+    // Give the world a chance to be saved when the window is closed
+    prevent_quit();
+    while !is_quit_requested() {
+        manager.update_view(&camera.visible_area());
+        camera.draw(&manager);
+        editor.draw(&camera);
         controller.update(&mut camera, &mut manager);
-        manager.clean();
+        editor.update(&mut camera, &mut manager);
+        manager.tick();
+        if let Err(e) = manager.clean() {
+            eprintln!("{e:#}");
+        }
         next_frame().await
     }
+    if let Err(e) = manager.save_all() {
+        eprintln!("{e:#}");
+    }
 }
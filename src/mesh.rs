@@ -1,134 +1,560 @@
 //! Module which defines meshing for the sand_engine
-use super::{blocks::Block, chunks::Chunk};
+use super::{blocks::Block, chunks::Chunk, Atlas, LightMap, MAX_LIGHT};
 use macroquad::{
     color::{Color, WHITE},
+    math::{vec2, vec3},
+    models::{Mesh, Vertex},
     prelude::Rect,
 };
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+};
+
+/// Source of [`ChunkMesh::id`]
+static NEXT_MESH_ID: AtomicU64 = AtomicU64::new(0);
 
-/// Distinguishes culled meshes from greedy one
+/// How much darker each block further from the surface gets
+const SHADE_STEP: f32 = 0.12;
+
+/// Distinguishes culled meshes from greedy one, also picks the mesher chunks are built with
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
 pub enum MeshType {
+    /// One quad per visible block
     CULLED,
+    /// Neighbouring blocks of the same type merged into rectangles
+    #[default]
     GREEDY,
 }
 
+/// A rectangle of blocks within a chunk, drawn with a flat color or tiled with a texture
+#[derive(Clone, Copy, Debug)]
+pub struct Quad {
+    pub color: Color,
+    pub rect: Rect,
+    /// Tile of the [`Atlas`] repeated once per block, [`Self::color`] is used without one
+    pub texture: Option<u16>,
+    /// Multiplies the color or texture, 1 leaves it untouched
+    pub brightness: f32,
+}
+impl Quad {
+    /// Quad drawn with the blocks color and texture
+    pub fn new(block: Block, rect: Rect) -> Self {
+        Self {
+            color: block.color(),
+            rect,
+            texture: block.definition().texture,
+            brightness: 1.,
+        }
+    }
+    /// Color the quad is drawn in with its brightness applied
+    pub fn shaded_color(&self) -> Color {
+        shade(self.color, self.brightness)
+    }
+}
+
+/// Darkens a color without touching its alpha
+fn shade(color: Color, brightness: f32) -> Color {
+    Color::new(
+        color.r * brightness,
+        color.g * brightness,
+        color.b * brightness,
+        color.a,
+    )
+}
+
+/// Blocks of a chunk together with [`ChunkMesh::MARGIN`] blocks of its neighbours on every side
+pub struct ChunkWindow {
+    blocks: Vec<Block>,
+    /// Generated surface height of every column in chunk local cords, if known
+    surface: Vec<Option<i64>>,
+    /// Whether a solid block sits above the window in every column
+    roofed: Vec<bool>,
+}
+impl ChunkWindow {
+    /// Width and height of the window in blocks
+    pub const SIZE: usize = 16 + 2 * ChunkMesh::MARGIN as usize;
+
+    /// Fills a window from chunk local cords, which run from `-MARGIN` to `15 + MARGIN`
+    pub fn new(mut block_at: impl FnMut(i64, i64) -> Block) -> Self {
+        let margin = ChunkMesh::MARGIN;
+        let blocks = (0..Self::SIZE * Self::SIZE)
+            .map(|i| {
+                let x = (i % Self::SIZE) as i64 - margin;
+                let y = (i / Self::SIZE) as i64 - margin;
+                block_at(x, y)
+            })
+            .collect();
+        Self {
+            blocks,
+            surface: vec![None; Self::SIZE],
+            roofed: vec![false; Self::SIZE],
+        }
+    }
+    /// Sets the surface height of every column from chunk local x cords, used
+    /// to keep sunlight out of caves whose roof is above the window
+    pub fn with_surface(mut self, mut surface_at: impl FnMut(i64) -> Option<i64>) -> Self {
+        let margin = ChunkMesh::MARGIN;
+        self.surface = (0..Self::SIZE)
+            .map(|x| surface_at(x as i64 - margin))
+            .collect();
+        self
+    }
+    /// Returns the surface height of a column by chunk local x cords
+    pub fn surface(&self, x: i64) -> Option<i64> {
+        self.surface[(x + ChunkMesh::MARGIN) as usize]
+    }
+    /// Marks the columns, from chunk local x cords, that have a solid block
+    /// above the window so sunlight does not reach into them
+    pub fn with_roofs(mut self, mut roofed_at: impl FnMut(i64) -> bool) -> Self {
+        let margin = ChunkMesh::MARGIN;
+        self.roofed = (0..Self::SIZE)
+            .map(|x| roofed_at(x as i64 - margin))
+            .collect();
+        self
+    }
+    /// Whether a column has a solid block above the window by chunk local x cords
+    pub fn roofed(&self, x: i64) -> bool {
+        self.roofed[(x + ChunkMesh::MARGIN) as usize]
+    }
+    /// Window of a chunk on its own, everything around it counts as air
+    pub fn isolated(chunk: &Chunk) -> Self {
+        Self::new(|x, y| {
+            if (0..16).contains(&x) && (0..16).contains(&y) {
+                chunk.get(&(x as u8), &(y as u8))
+            } else {
+                Block::AIR
+            }
+        })
+    }
+    /// Returns a block by chunk local cords
+    pub fn get(&self, x: i64, y: i64) -> Block {
+        let margin = ChunkMesh::MARGIN;
+        self.blocks[(x + margin) as usize + (y + margin) as usize * Self::SIZE]
+    }
+    /// Blocks of the chunk itself
+    pub fn chunk_blocks(&self) -> [Block; 16 * 16] {
+        std::array::from_fn(|i| self.get((i % 16) as i64, (i / 16) as i64))
+    }
+    /// How far each block of the chunk is below the surface, counted in steps
+    /// to the nearest non solid block and capped at [`ChunkMesh::SHADE_DEPTH`]
+    ///
+    /// Blocks touching air are at depth 0. Only the window is searched, which
+    /// is enough since anything further away is past the cap anyway.
+    pub fn depths(&self) -> [u8; 16 * 16] {
+        let size = Self::SIZE;
+        let cap = ChunkMesh::SHADE_DEPTH;
+        let mut distance: Vec<u8> = self
+            .blocks
+            .iter()
+            .map(|block| if block.is_solid() { u8::MAX } else { 0 })
+            .collect();
+        let mut queue: VecDeque<usize> =
+            (0..distance.len()).filter(|i| distance[*i] == 0).collect();
+        while let Some(i) = queue.pop_front() {
+            let (x, y) = (i % size, i / size);
+            let next = distance[i] + 1;
+            if next > cap + 1 {
+                continue;
+            }
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < size).then(|| i + 1),
+                (y > 0).then(|| i - size),
+                (y + 1 < size).then(|| i + size),
+            ];
+            for neighbour in neighbours.into_iter().flatten() {
+                if distance[neighbour] > next {
+                    distance[neighbour] = next;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        let margin = ChunkMesh::MARGIN;
+        std::array::from_fn(|i| {
+            let x = (i % 16) as i64 + margin;
+            let y = (i / 16) as i64 + margin;
+            match distance[x as usize + y as usize * size] {
+                0 => 0,
+                d => (d - 1).min(cap),
+            }
+        })
+    }
+}
+
 /// Represents rectangles to be drawn within a chunk
 pub struct ChunkMesh {
+    /// Unique for every mesh ever built, a new id means the chunk changed
+    pub id: u64,
     pub mesh_type: MeshType,
-    pub mesh: Vec<(Color, Rect)>,
+    pub mesh: Vec<Quad>,
 }
 impl ChunkMesh {
+    /// How many blocks past its own chunk a mesh depends on
+    pub const MARGIN: i64 = MAX_LIGHT as i64;
+    /// Depth below the surface past which blocks stop getting darker
+    pub const SHADE_DEPTH: u8 = 4;
+
+    /// Creates a chunk mesh with the given mesher
+    pub fn build(chunk: &Chunk, mesh_type: MeshType) -> ChunkMesh {
+        Self::build_with(chunk, mesh_type, Decorations::builtin())
+    }
+    /// Creates a chunk mesh with the given mesher and decorations
+    pub fn build_with(chunk: &Chunk, mesh_type: MeshType, decorations: &Decorations) -> ChunkMesh {
+        match mesh_type {
+            MeshType::CULLED => Self::culled_mesh_with(chunk, decorations),
+            MeshType::GREEDY => Self::greedy_mesh_with(chunk, decorations),
+        }
+    }
+    /// Creates a chunk mesh that darkens blocks the deeper they are below the
+    /// surface and, given a light map, the less light reaches them
+    ///
+    /// The window lets the shading look into neighbouring chunks so borders line up.
+    pub fn build_shaded(
+        window: &ChunkWindow,
+        light: Option<&LightMap>,
+        mesh_type: MeshType,
+        decorations: &Decorations,
+    ) -> ChunkMesh {
+        let blocks = window.chunk_blocks();
+        let depths = window.depths();
+        let brightness = std::array::from_fn(|i| {
+            let lit = light.map_or(1., |light| light.brightness(i));
+            lit * (1. - SHADE_STEP * depths[i] as f32)
+        });
+        let mesh = match mesh_type {
+            MeshType::CULLED => culled_quads(&blocks, &brightness, decorations),
+            MeshType::GREEDY => greedy_quads(&blocks, &brightness, decorations),
+        };
+        ChunkMesh::new(mesh_type, mesh)
+    }
     /// Creates a chunk mesh but uses the greedy algorithm to solve it
     pub fn greedy_mesh(chunk: &Chunk) -> ChunkMesh {
-        let mut blocks = chunk.blocks.clone();
-        let mut mesh: Vec<(Color, Rect)> = Vec::new();
-
-        for x in 0..16 {
-            let mut y = 0;
-            while y < 16 {
-                if blocks[x + y * 16] == Block::Air {
-                    y += 1;
-                    continue;
-                } else {
-                    let block_type = blocks[x + y * 16].clone();
-                    let mut w = 1;
-                    let mut h = 1;
-
-                    // Determine height
-                    while y + h < 16 && blocks[x + (y + h) * 16] == block_type {
-                        h += 1;
-                    }
-
-                    // Determine width
-                    'w: for wx in (x + 1)..16 {
-                        for wy in y..(y + h) {
-                            if blocks[wx + wy * 16] != block_type {
-                                break 'w;
-                            }
-                        }
-                        w += 1;
-                    }
-
-                    // Mark visited
-                    for dx in 0..w {
-                        for dy in 0..h {
-                            blocks[(x + dx) + (y + dy) * 16] = Block::Air;
-                        }
-                    }
-
-                    let rects = match block_type {
-                        Block::WaterEdge => {
-                            vec![
-                                (
-                                    block_type.color(),
-                                    Rect {
-                                        x: x as f32,
-                                        y: y as f32,
-                                        w: w as f32,
-                                        h: h as f32 - 0.3,
-                                    },
-                                ),
-                                (
-                                    WHITE,
-                                    Rect {
-                                        x: x as f32,
-                                        y: y as f32 + 0.6,
-                                        w: w as f32,
-                                        h: 0.2,
-                                    },
-                                ),
-                            ]
-                        }
-                        _ => vec![(
-                            block_type.color(),
-                            Rect {
-                                x: x as f32,
-                                y: y as f32,
-                                w: w as f32,
-                                h: h as f32,
-                            },
-                        )],
+        Self::greedy_mesh_with(chunk, Decorations::builtin())
+    }
+    /// Greedy mesh with the given decorations
+    pub fn greedy_mesh_with(chunk: &Chunk, decorations: &Decorations) -> ChunkMesh {
+        let mesh = greedy_quads(&chunk.blocks(), &[1.; 16 * 16], decorations);
+        ChunkMesh::new(MeshType::GREEDY, mesh)
+    }
+    /// Creates a chunk mesh but uses a culled algorithm to solve
+    ///
+    /// Every visible block gets its own quad, invisible blocks like air are
+    /// skipped. In a side view nothing is hidden behind other blocks so this
+    /// is the most quads a chunk can need.
+    pub fn culled_mesh(chunk: &Chunk) -> ChunkMesh {
+        Self::culled_mesh_with(chunk, Decorations::builtin())
+    }
+    /// Culled mesh with the given decorations
+    pub fn culled_mesh_with(chunk: &Chunk, decorations: &Decorations) -> ChunkMesh {
+        let mesh = culled_quads(&chunk.blocks(), &[1.; 16 * 16], decorations);
+        ChunkMesh::new(MeshType::CULLED, mesh)
+    }
+    /// Generates an empty chunk mesh
+    pub fn empty() -> Self {
+        ChunkMesh::new(MeshType::CULLED, vec![])
+    }
+    fn new(mesh_type: MeshType, mesh: Vec<Quad>) -> Self {
+        ChunkMesh {
+            id: NEXT_MESH_ID.fetch_add(1, Ordering::Relaxed),
+            mesh_type,
+            mesh,
+        }
+    }
+    /// Builds the triangles of every rect in flat colors, ready to be drawn with a single call
+    ///
+    /// The chunk covers 0 to 16 on both axes with its top left corner at the
+    /// origin, laid out the way it appears on screen. Nothing is uploaded so
+    /// this works without a window.
+    pub fn to_mesh(&self) -> Mesh {
+        self.to_meshes(None).0
+    }
+    /// Builds the flat colored and the textured triangles of the chunk
+    ///
+    /// Quads with a tile in the atlas are split into one quad per block so the
+    /// tile repeats instead of stretching, the rest fall back to their color.
+    /// The textured mesh still needs the atlas texture set before drawing.
+    pub fn to_meshes(&self, atlas: Option<&Atlas>) -> (Mesh, Mesh) {
+        let mut flat = Geometry::default();
+        let mut textured = Geometry::default();
+        for quad in &self.mesh {
+            let rect = quad.rect;
+            let tile = quad
+                .texture
+                .zip(atlas)
+                .and_then(|(texture, atlas)| atlas.uv(texture));
+            let Some(uv) = tile else {
+                flat.push(rect, Rect::default(), quad.shaded_color());
+                continue;
+            };
+
+            let mut cell_y = 0.;
+            while cell_y < rect.h {
+                let h = (rect.h - cell_y).min(1.);
+                let mut cell_x = 0.;
+                while cell_x < rect.w {
+                    let w = (rect.w - cell_x).min(1.);
+                    // Cut off cells keep the part of the tile nearest the rects origin,
+                    // which is the bottom right on screen
+                    let cell_uv = Rect {
+                        x: uv.x + uv.w * (1. - w),
+                        y: uv.y + uv.h * (1. - h),
+                        w: uv.w * w,
+                        h: uv.h * h,
                     };
+                    let cell = Rect {
+                        x: rect.x + cell_x,
+                        y: rect.y + cell_y,
+                        w,
+                        h,
+                    };
+                    textured.push(cell, cell_uv, shade(WHITE, quad.brightness));
+                    cell_x += 1.;
+                }
+                cell_y += 1.;
+            }
+        }
+        (flat.into(), textured.into())
+    }
+    /// Paints the mesh into a 16 by 16 RGBA image of one pixel per block, laid
+    /// out the same way as [`Self::to_mesh`]
+    ///
+    /// A pixel takes the color of the last rect covering its center, pixels
+    /// no rect covers stay transparent.
+    pub fn rasterize(&self) -> [u8; 16 * 16 * 4] {
+        let mut pixels = [0; 16 * 16 * 4];
+        for quad in &self.mesh {
+            let rect = quad.rect;
+            let rgba: [u8; 4] = quad.shaded_color().into();
+            let left = 16.0 - rect.x - rect.w;
+            let top = 16.0 - rect.y - rect.h;
+            let first_x = (left - 0.5).ceil().max(0.) as usize;
+            let first_y = (top - 0.5).ceil().max(0.) as usize;
+            let last_x = ((left + rect.w - 0.5).ceil() as usize).min(16);
+            let last_y = ((top + rect.h - 0.5).ceil() as usize).min(16);
+            for y in first_y..last_y {
+                for x in first_x..last_x {
+                    let i = (x + y * 16) * 4;
+                    pixels[i..i + 4].copy_from_slice(&rgba);
+                }
+            }
+        }
+        pixels
+    }
+}
 
-                    mesh.extend(rects);
+/// Whether a block draws anything at all
+fn is_visible(block: &Block) -> bool {
+    block.color().a > 0.
+}
+
+/// Quads of every visible block drawn at its brightness
+fn culled_quads(
+    blocks: &[Block; 16 * 16],
+    brightness: &[f32; 16 * 16],
+    decorations: &Decorations,
+) -> Vec<Quad> {
+    let mut quads = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        if !is_visible(block) {
+            continue;
+        }
+        let rect = Rect {
+            x: (i % 16) as f32,
+            y: (i / 16) as f32,
+            w: 1.,
+            h: 1.,
+        };
+        quads.extend(decorations.shaded_quads(*block, rect, brightness[i]));
+    }
+    quads
+}
+
+/// Quads of the visible blocks merged into as few rects as the greedy search finds
+///
+/// The chunk is merged once growing rects along rows first and once along
+/// columns first, whichever needs fewer rects is kept.
+fn greedy_quads(
+    blocks: &[Block; 16 * 16],
+    brightness: &[f32; 16 * 16],
+    decorations: &Decorations,
+) -> Vec<Quad> {
+    let rows = greedy_rects(blocks, brightness, false);
+    let columns = greedy_rects(blocks, brightness, true);
+    let rects = if columns.len() < rows.len() {
+        columns
+    } else {
+        rows
+    };
+    rects
+        .into_iter()
+        .flat_map(|((block, brightness), rect)| decorations.shaded_quads(block, rect, brightness))
+        .collect()
+}
+
+/// Splits the visible blocks into rects of a single block type and brightness
+///
+/// Rects grow along a row as far as they can, then upwards while the whole
+/// row above matches. With `transpose` rows and columns swap roles.
+fn greedy_rects(
+    blocks: &[Block; 16 * 16],
+    brightness: &[f32; 16 * 16],
+    transpose: bool,
+) -> Vec<((Block, f32), Rect)> {
+    // `u` runs along a row and `v` across rows, swapped when transposed
+    let at = |u: usize, v: usize| {
+        let i = if transpose { v + u * 16 } else { u + v * 16 };
+        (blocks[i], brightness[i])
+    };
+    let mut done = [false; 16 * 16];
+    let mut rects = Vec::new();
+
+    for v in 0..16 {
+        let mut u = 0;
+        while u < 16 {
+            let block = at(u, v);
+            if done[u + v * 16] || !is_visible(&block.0) {
+                u += 1;
+                continue;
+            }
+
+            let mut length = 1;
+            while u + length < 16 && !done[u + length + v * 16] && at(u + length, v) == block {
+                length += 1;
+            }
+            let mut depth = 1;
+            while v + depth < 16
+                && (u..u + length).all(|i| !done[i + (v + depth) * 16] && at(i, v + depth) == block)
+            {
+                depth += 1;
+            }
 
-                    y += h;
+            for dv in 0..depth {
+                for du in 0..length {
+                    done[(u + du) + (v + dv) * 16] = true;
                 }
             }
+            let (x, y, w, h) = if transpose {
+                (v, u, depth, length)
+            } else {
+                (u, v, length, depth)
+            };
+            rects.push((
+                block,
+                Rect {
+                    x: x as f32,
+                    y: y as f32,
+                    w: w as f32,
+                    h: h as f32,
+                },
+            ));
+            u += length;
         }
-        ChunkMesh {
-            mesh_type: MeshType::GREEDY,
-            mesh,
+    }
+    rects
+}
+
+/// Turns a rect of a single block type into the quads drawn for it
+pub type Decoration = fn(Block, Rect) -> Vec<Quad>;
+
+/// Per block hooks changing how rects are drawn, blocks without one are drawn as a single quad
+#[derive(Default)]
+pub struct Decorations {
+    hooks: HashMap<Block, Decoration>,
+}
+impl Decorations {
+    /// Decorations used by default, currently the foam stripe on top of water
+    pub fn builtin() -> &'static Self {
+        static BUILTIN: OnceLock<Decorations> = OnceLock::new();
+        BUILTIN.get_or_init(|| {
+            let mut decorations = Self::default();
+            decorations.register(Block::WATER_EDGE, water_foam);
+            decorations
+        })
+    }
+    /// Draws every rect of `block` with `decoration`, replacing any earlier hook
+    pub fn register(&mut self, block: Block, decoration: Decoration) {
+        self.hooks.insert(block, decoration);
+    }
+    /// Returns the quads drawn for a rect filled with `block`
+    pub fn quads(&self, block: Block, rect: Rect) -> Vec<Quad> {
+        match self.hooks.get(&block) {
+            Some(decoration) => decoration(block, rect),
+            None => vec![Quad::new(block, rect)],
         }
     }
-    /// Creates a chunk mesh but uses a culled algorithm to solve
-    pub fn culled_mesh(chunk: &Chunk) -> ChunkMesh {
-        let mesh = chunk
-            .blocks
-            .clone()
-            .into_iter()
-            .enumerate()
-            .map(|(i, block)| {
-                (
-                    block.color(),
-                    Rect {
-                        x: (i % 16) as f32,
-                        y: (i / 16) as f32,
-                        w: 1.,
-                        h: 1.,
-                    },
-                )
-            })
-            .collect();
-        ChunkMesh {
-            mesh_type: MeshType::CULLED,
-            mesh,
+    /// Quads of a rect darkened to the given brightness
+    fn shaded_quads(&self, block: Block, rect: Rect, brightness: f32) -> Vec<Quad> {
+        let mut quads = self.quads(block, rect);
+        for quad in &mut quads {
+            quad.brightness *= brightness;
         }
+        quads
     }
-    /// Generates an empty chunk mesh
-    pub fn empty() -> Self {
-        ChunkMesh {
-            mesh_type: MeshType::CULLED,
-            mesh: vec![],
+}
+
+/// Water surface that stops a little below the top with a white stripe of foam
+fn water_foam(block: Block, rect: Rect) -> Vec<Quad> {
+    vec![
+        Quad::new(
+            block,
+            Rect {
+                h: rect.h - 0.3,
+                ..rect
+            },
+        ),
+        Quad {
+            color: WHITE,
+            rect: Rect {
+                y: rect.y + rect.h - 0.4,
+                h: 0.2,
+                ..rect
+            },
+            texture: None,
+            brightness: 1.,
+        },
+    ]
+}
+
+/// Vertices and indices of a mesh being built
+#[derive(Default)]
+struct Geometry {
+    vertices: Vec<Vertex>,
+    indices: Vec<u16>,
+}
+impl Geometry {
+    /// Adds a rect given in chunk cords, flipped to how it appears on screen
+    fn push(&mut self, rect: Rect, uv: Rect, color: Color) {
+        // World x and y grow towards the left and top of the screen
+        let left = 16.0 - rect.x - rect.w;
+        let top = 16.0 - rect.y - rect.h;
+        let first = self.vertices.len() as u16;
+        for (x, y, u, v) in [
+            (left, top, uv.x, uv.y),
+            (left + rect.w, top, uv.x + uv.w, uv.y),
+            (left + rect.w, top + rect.h, uv.x + uv.w, uv.y + uv.h),
+            (left, top + rect.h, uv.x, uv.y + uv.h),
+        ] {
+            self.vertices.push(Vertex {
+                position: vec3(x, y, 0.),
+                uv: vec2(u, v),
+                color,
+            });
+        }
+        self.indices
+            .extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+}
+impl From<Geometry> for Mesh {
+    fn from(geometry: Geometry) -> Self {
+        Mesh {
+            vertices: geometry.vertices,
+            indices: geometry.indices,
+            texture: None,
         }
     }
 }
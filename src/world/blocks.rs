@@ -1,42 +1,125 @@
 //! Module which defines blocks and their respective colors
-use macroquad::prelude::{Color, PURPLE};
-use serde::{Deserialize, Serialize};
-
-/// Block types within sand engine
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
-#[repr(u8)] // Ensures the struct is of size u8
-pub enum Block {
-    Air,
-    GrassBlock,
-    Dirt,
-    Stone,
-    Water,
-    WaterEdge,
-    Sand,
-    SandStone,
-    OakWood,
-    OakLeave,
-    Fire,
+use super::registry::{registry, BlockDefinition};
+use macroquad::prelude::Color;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// Block type within sand engine, an id into the [`BlockRegistry`](super::registry::BlockRegistry)
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Block(pub u16);
+
+impl Block {
+    pub const AIR: Block = Block(0);
+    pub const GRASS_BLOCK: Block = Block(1);
+    pub const DIRT: Block = Block(2);
+    pub const STONE: Block = Block(3);
+    pub const WATER: Block = Block(4);
+    pub const WATER_EDGE: Block = Block(5);
+    pub const SAND: Block = Block(6);
+    pub const SAND_STONE: Block = Block(7);
+    pub const OAK_WOOD: Block = Block(8);
+    pub const OAK_LEAVE: Block = Block(9);
+    pub const FIRE: Block = Block(10);
+    pub const SNOW: Block = Block(11);
+    pub const ICE: Block = Block(12);
+    pub const COAL_ORE: Block = Block(13);
+    pub const IRON_ORE: Block = Block(14);
+    pub const GOLD_ORE: Block = Block(15);
+    pub const DIAMOND_ORE: Block = Block(16);
+    pub const CACTUS: Block = Block(17);
 }
+
+/// Describes how a block is moved by the simulation
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockClass {
+    /// Never moves on its own and can not be pushed aside
+    Static,
+    /// Empty space that anything heavier can move into
+    Gas,
+    /// Falls down and slides off slopes like sand
+    Granular,
+    /// Flows down and spreads sideways
+    Liquid,
+}
+
 impl Block {
+    /// Returns the registry entry of the block
+    pub fn definition(&self) -> &'static BlockDefinition {
+        registry().get(self)
+    }
+    /// Returns the blocks name
+    pub fn name(&self) -> &'static str {
+        &self.definition().name
+    }
     /// Returns the blocks respective color
     pub fn color(&self) -> Color {
-        match self {
-            Block::Air => Color::new(0., 0., 0., 0.),
-            Block::GrassBlock => Color::from_hex(0x307a2a),
-            Block::Dirt => Color::from_hex(0xba7938),
-            Block::Stone => Color::from_hex(0x515357),
-            Block::Water | Block::WaterEdge => Color::from_hex(0x4b53eb),
-            Block::Sand => Color::from_hex(0xbbc26d),
-            Block::SandStone => Color::from_hex(0xe1e897),
-            _ => PURPLE,
-        }
+        self.definition().color
     }
     /// Determines if a block should be treated as transparent or not for collisions
     pub fn is_solid(&self) -> bool {
-        match self {
-            Block::Water | Block::Air | Block::WaterEdge => false,
-            _ => true,
+        self.definition().solid
+    }
+    /// Returns how the simulation treats the block
+    pub fn class(&self) -> BlockClass {
+        self.definition().class
+    }
+    /// Relative weight of a block, heavier blocks sink through lighter non static ones
+    pub fn density(&self) -> u8 {
+        self.definition().density
+    }
+    /// Light level the block gives off
+    pub fn light(&self) -> u8 {
+        self.definition().light
+    }
+    /// Determines if this block is able to move into the space held by `other`
+    pub fn can_displace(&self, other: &Block) -> bool {
+        self.density() > other.density() && other.class() != BlockClass::Static
+    }
+}
+
+impl fmt::Debug for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Binary formats store the id the same way the old `Block` enum stored its
+/// variant so existing region files keep loading, text formats use the name
+impl Serialize for Block {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(self.name())
+        } else {
+            serializer.serialize_u32(self.0 as u32)
+        }
+    }
+}
+impl<'de> Deserialize<'de> for Block {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BlockVisitor;
+        impl de::Visitor<'_> for BlockVisitor {
+            type Value = Block;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a block id or name")
+            }
+            fn visit_u64<E: de::Error>(self, id: u64) -> Result<Block, E> {
+                u16::try_from(id)
+                    .map(Block)
+                    .map_err(|_| E::custom(format!("block id {id} is out of range")))
+            }
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Block, E> {
+                registry()
+                    .by_name(name)
+                    .ok_or_else(|| E::custom(format!("unknown block {name:?}")))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(BlockVisitor)
+        } else {
+            deserializer.deserialize_u32(BlockVisitor)
         }
     }
 }
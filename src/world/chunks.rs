@@ -6,22 +6,199 @@ use serde_with::serde_as;
 
 use super::blocks::Block;
 use chrono::{DateTime, Local};
+use std::mem;
 
 /// Structure that represents a World Chunk
-#[serde_as]
+///
+/// Blocks are stored as indices into a palette of the blocks the chunk holds,
+/// packed as tightly as the palette allows. Chunks made of a single block only
+/// store that block.
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(from = "ChunkData", into = "ChunkData")]
 pub struct Chunk {
-    #[serde_as(as = "[_; 16*16]")]
-    pub blocks: [Block; 16 * 16],
-    #[serde(skip_serializing, default = "Local::now", skip_deserializing)]
+    storage: BlockStorage,
     pub last_used: DateTime<Local>,
+    /// Set when the chunk differs from what the generator makes, unmodified
+    /// chunks can be dropped and generated again whenever they are needed
+    pub modified: bool,
 }
 impl Chunk {
     /// Creates a new chunk given an array of Blocks
     pub fn new(blocks: [Block; 16 * 16]) -> Self {
         Self {
-            blocks,
+            storage: BlockStorage::pack(&blocks),
             last_used: Local::now(),
+            modified: false,
+        }
+    }
+    /// Returns the block at the given chunk cords
+    pub fn get(&self, x: &u8, y: &u8) -> Block {
+        self.storage.get((x + 16 * y) as usize)
+    }
+    /// Places a block and marks the chunk modified if it changed
+    ///
+    /// Returns the previous block
+    pub fn set(&mut self, x: &u8, y: &u8, block: Block) -> Block {
+        let previous = self.storage.set((x + 16 * y) as usize, block);
+        if previous != block {
+            self.modified = true;
+        }
+        previous
+    }
+    /// Returns the block the whole chunk is made of, if it holds only one
+    pub fn uniform(&self) -> Option<Block> {
+        match self.storage {
+            BlockStorage::Uniform(block) => Some(block),
+            BlockStorage::Palette { .. } => None,
+        }
+    }
+    /// Unpacks every block, row by row from the bottom
+    pub fn blocks(&self) -> [Block; 16 * 16] {
+        std::array::from_fn(|i| self.storage.get(i))
+    }
+    /// Repacks the blocks, dropping palette entries no block uses anymore
+    pub fn compact(&mut self) {
+        self.storage = BlockStorage::pack(&self.blocks());
+    }
+    /// Rough number of bytes the chunk takes up in memory
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.storage.heap_size()
+    }
+}
+
+/// Blocks of a chunk compressed with a palette
+#[derive(Clone, Debug)]
+enum BlockStorage {
+    Uniform(Block),
+    Palette {
+        palette: Vec<Block>,
+        /// Bits per block, 1, 2, 4 or 8
+        bits: u8,
+        /// Palette indices of every block, packed from the lowest bit up
+        indices: Box<[u8]>,
+    },
+}
+impl BlockStorage {
+    fn pack(blocks: &[Block; 16 * 16]) -> Self {
+        let mut palette: Vec<Block> = Vec::new();
+        for block in blocks {
+            if !palette.contains(block) {
+                palette.push(*block);
+            }
+        }
+        if palette.len() == 1 {
+            return BlockStorage::Uniform(palette[0]);
+        }
+
+        let bits = Self::bits_for(palette.len());
+        let mut indices = vec![0; 16 * 16 * bits as usize / 8].into_boxed_slice();
+        for (i, block) in blocks.iter().enumerate() {
+            let index = palette.iter().position(|entry| entry == block).unwrap();
+            Self::write(&mut indices, bits, i, index as u8);
+        }
+        BlockStorage::Palette {
+            palette,
+            bits,
+            indices,
+        }
+    }
+
+    /// Smallest bit width that fits `len` palette entries
+    fn bits_for(len: usize) -> u8 {
+        match len {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        }
+    }
+
+    fn read(indices: &[u8], bits: u8, i: usize) -> u8 {
+        let bit = i * bits as usize;
+        let mask = ((1u16 << bits) - 1) as u8;
+        (indices[bit / 8] >> (bit % 8)) & mask
+    }
+
+    fn write(indices: &mut [u8], bits: u8, i: usize, index: u8) {
+        let bit = i * bits as usize;
+        let mask = ((1u16 << bits) - 1) as u8;
+        let byte = &mut indices[bit / 8];
+        *byte = (*byte & !(mask << (bit % 8))) | (index << (bit % 8));
+    }
+
+    fn get(&self, i: usize) -> Block {
+        match self {
+            BlockStorage::Uniform(block) => *block,
+            BlockStorage::Palette {
+                palette,
+                bits,
+                indices,
+            } => palette[Self::read(indices, *bits, i) as usize],
+        }
+    }
+
+    fn set(&mut self, i: usize, block: Block) -> Block {
+        let previous = self.get(i);
+        if previous == block {
+            return previous;
+        }
+        if let BlockStorage::Palette {
+            palette,
+            bits,
+            indices,
+        } = self
+        {
+            let index = match palette.iter().position(|entry| entry == &block) {
+                Some(index) => index,
+                None => {
+                    palette.push(block);
+                    palette.len() - 1
+                }
+            };
+            // Blocks the palette no longer holds stay in it until the chunk is
+            // compacted, a palette past what 8 bits can index is repacked instead
+            if palette.len() <= 256 && Self::bits_for(palette.len()) == *bits {
+                Self::write(indices, *bits, i, index as u8);
+                return previous;
+            }
+        }
+
+        let mut blocks: [Block; 16 * 16] = std::array::from_fn(|i| self.get(i));
+        blocks[i] = block;
+        *self = Self::pack(&blocks);
+        previous
+    }
+
+    fn heap_size(&self) -> usize {
+        match self {
+            BlockStorage::Uniform(_) => 0,
+            BlockStorage::Palette {
+                palette, indices, ..
+            } => palette.capacity() * mem::size_of::<Block>() + indices.len(),
+        }
+    }
+}
+
+/// Layout chunks were saved with before [`region_file`](super::region_file)
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+struct ChunkData {
+    #[serde_as(as = "[_; 16*16]")]
+    blocks: [Block; 16 * 16],
+}
+impl From<ChunkData> for Chunk {
+    /// Loaded chunks may hold changes, so they can not be generated again
+    fn from(data: ChunkData) -> Self {
+        Self {
+            modified: true,
+            ..Self::new(data.blocks)
+        }
+    }
+}
+impl From<Chunk> for ChunkData {
+    fn from(chunk: Chunk) -> Self {
+        Self {
+            blocks: chunk.blocks(),
         }
     }
 }
@@ -1,63 +1,356 @@
 //! Generator functions for creating world from seed
 
-use super::{blocks::Block, chunks::Chunk};
+use super::{blocks::Block, chunks::Chunk, region_file::crc32};
+use anyhow::{bail, Context, Result};
 use noise::{self, NoiseFn};
+use serde::{Deserialize, Serialize};
+use std::{array, cmp::Ordering, path::Path};
+
+pub mod biome;
+pub mod features;
+pub mod legacy;
+pub mod terrain;
+pub mod underground;
+use biome::{Biome, BiomeMap};
+use features::{FeaturePlacer, Structure, StructureDef};
+use legacy::LegacyWorldGenerator;
+use terrain::TerrainConfig;
+use underground::Underground;
 
 /// Trait that defines requirements of a world generator
-pub trait Generator {
+pub trait Generator: Send + Sync {
+    /// Block of the terrain at a position
+    ///
+    /// Generators that place features across chunk borders only do so in
+    /// [`Generator::gen_chunk`], so this may disagree with the chunk near them
     fn gen_block(&self, world_x: &i64, world_y: &i64) -> Block;
+    /// Every block of a chunk, what the world is built from
     fn gen_chunk(&self, region_x: &i32, region_y: &i32, chunk_x: &u8, chunk_y: &u8) -> Chunk {
-        let base_pos_x = ((*region_x as i64) << 8) | (*chunk_x as i64) << 4;
-        let base_pos_y = ((*region_y as i64) << 8) | (*chunk_y as i64) << 4;
+        let (base_pos_x, base_pos_y) = chunk_origin(region_x, region_y, chunk_x, chunk_y);
 
-        let mut blocks = Vec::with_capacity(16 * 16 as usize);
-        for i in 0..16 * 16 as usize {
+        let mut blocks = Vec::with_capacity(16 * 16);
+        for i in 0..16 * 16 {
             let world_x = base_pos_x | (i % 16) as i64;
             let world_y = base_pos_y | (i / 16) as i64;
             blocks.push(self.gen_block(&world_x, &world_y));
         }
         Chunk::new(blocks.try_into().unwrap())
     }
+    /// Biome of a column, if the generator has biomes
+    fn biome(&self, _world_x: &i64) -> Option<Biome> {
+        None
+    }
+    /// Height of the highest generated terrain block of a column, ignoring
+    /// features, if the generator knows it
+    fn surface_height(&self, _world_x: &i64) -> Option<i64> {
+        None
+    }
+}
+
+/// World cords of the bottom left block of a chunk
+pub fn chunk_origin(region_x: &i32, region_y: &i32, chunk_x: &u8, chunk_y: &u8) -> (i64, i64) {
+    (
+        ((*region_x as i64) << 8) | (*chunk_x as i64) << 4,
+        ((*region_y as i64) << 8) | (*chunk_y as i64) << 4,
+    )
+}
+
+/// Version of [`WorldGenerator`] new worlds are created with
+///
+/// Has to go up whenever the blocks it generates change, worlds keep the
+/// version they were created with. Version 1 is the terrain of
+/// [`LegacyWorldGenerator`], version 2 added biomes, caves and features.
+pub const GENERATOR_VERSION: u32 = 2;
+
+/// Checksum of a few chunks around the surface near the origin, changes when
+/// the generator makes different blocks than before
+pub fn fingerprint(gen: &dyn Generator) -> u32 {
+    let mut bytes = Vec::new();
+    for chunk_x in 0..4 {
+        let surface = gen.surface_height(&(chunk_x as i64 * 16 + 8)).unwrap_or(0);
+        for world_y in [surface - 16, surface] {
+            let (region_y, chunk_y) = ((world_y >> 8) as i32, ((world_y >> 4) & 15) as u8);
+            let chunk = gen.gen_chunk(&0, &region_y, &chunk_x, &chunk_y);
+            bytes.extend(
+                chunk
+                    .blocks()
+                    .iter()
+                    .flat_map(|block| block.0.to_le_bytes()),
+            );
+        }
+    }
+    crc32(&bytes)
+}
+
+/// Identifies which generator a world was created with, stored in world metadata
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum GeneratorKind {
+    /// Noise based terrain of [`WorldGenerator`]
+    Default {
+        /// Worlds created before versioning are treated as version 1
+        #[serde(default = "legacy_version")]
+        version: u32,
+        #[serde(default)]
+        terrain: TerrainConfig,
+        /// Prefab structures the world was created with, kept with the world
+        /// so changing the structure files does not change its terrain
+        #[serde(default)]
+        structures: Vec<StructureDef>,
+    },
+    /// Stacked layers of [`FlatWorldGenerator`]
+    Flat { base: i64, layers: Vec<FlatLayer> },
+}
+impl Default for GeneratorKind {
+    fn default() -> Self {
+        GeneratorKind::Default {
+            version: GENERATOR_VERSION,
+            terrain: TerrainConfig::default(),
+            structures: Vec::new(),
+        }
+    }
+}
+fn legacy_version() -> u32 {
+    1
+}
+impl GeneratorKind {
+    /// Default generator of worlds created before the generator was recorded
+    /// or versioned
+    pub fn legacy() -> Self {
+        GeneratorKind::Default {
+            version: legacy_version(),
+            terrain: TerrainConfig::default(),
+            structures: Vec::new(),
+        }
+    }
+    /// A flat world of one stone layer below 0 then 3 dirt and 1 grass
+    pub fn flat() -> Self {
+        let generator = FlatWorldGenerator::default();
+        GeneratorKind::Flat {
+            base: generator.base,
+            layers: generator.layers,
+        }
+    }
+    /// The default generator with every prefab structure of a folder, see
+    /// [`features::STRUCTURES_DIR`]
+    pub fn with_structures(dir: impl AsRef<Path>) -> Result<Self> {
+        Ok(GeneratorKind::Default {
+            version: GENERATOR_VERSION,
+            terrain: TerrainConfig::default(),
+            structures: StructureDef::load_dir(dir)?,
+        })
+    }
+    /// Creates the generator this kind describes
+    ///
+    /// Fails for generator versions this build does not know and for invalid
+    /// settings
+    pub fn build(&self, seed: u32) -> Result<Box<dyn Generator>> {
+        Ok(match self {
+            GeneratorKind::Default { version: 1, .. } => Box::new(LegacyWorldGenerator::new(seed)),
+            GeneratorKind::Default {
+                version: GENERATOR_VERSION,
+                terrain,
+                structures,
+            } => {
+                terrain.validate()?;
+                let structures = structures
+                    .iter()
+                    .map(Structure::from_def)
+                    .collect::<Result<_>>()
+                    .context("world has a broken structure")?;
+                Box::new(WorldGenerator::new(seed, terrain.clone(), structures))
+            }
+            GeneratorKind::Default { version, .. } => bail!(
+                "world uses generator version {version} but only 1 to {GENERATOR_VERSION} are supported"
+            ),
+            GeneratorKind::Flat { base, layers } => {
+                Box::new(FlatWorldGenerator::new(*base, layers.clone()))
+            }
+        })
+    }
+}
+
+/// Everything about a column of terrain that does not depend on height
+struct Column {
+    height: i64,
+    biome: Biome,
+    /// Small random shift of the beach border
+    beach_jitter: i64,
 }
 
 pub struct WorldGenerator {
     gen: noise::Simplex,
+    terrain: TerrainConfig,
+    biomes: BiomeMap,
+    underground: Underground,
+    features: FeaturePlacer,
 }
 impl Generator for WorldGenerator {
+    /// Terrain without trees, cacti and structures
     fn gen_block(&self, world_x: &i64, world_y: &i64) -> Block {
-        match self.get_height(world_x) {
-            h if &h >= world_y => {
-                let delta = h - world_y;
-
-                match (world_y - self.noise1d(world_x, 3., 1.), delta) {
-                    (y, d) if d < 4 && y < 54 => Block::Sand,
-                    (y, d) if d < 9 && y < 54 => Block::SandStone,
-                    (_, d) if d < 3 => Block::GrassBlock,
-                    (_, d) if d < 8 => Block::Dirt,
-                    (_, _) => Block::Stone,
-                }
-            }
-            _ => match world_y {
-                51i64..=i64::MAX => Block::Air,
-                50 => Block::WaterEdge,
-                i64::MIN..=49i64 => Block::Water,
-            },
-        }
+        self.block_in_column(&self.get_column(world_x), world_x, world_y)
+    }
+    fn gen_chunk(&self, region_x: &i32, region_y: &i32, chunk_x: &u8, chunk_y: &u8) -> Chunk {
+        // Columns are shared by every block above each other so only work them out once
+        let (base_pos_x, base_pos_y) = chunk_origin(region_x, region_y, chunk_x, chunk_y);
+        let columns: [Column; 16] = array::from_fn(|i| self.get_column(&(base_pos_x | i as i64)));
+
+        let mut blocks = array::from_fn(|i| {
+            let world_x = base_pos_x | (i % 16) as i64;
+            let world_y = base_pos_y | (i / 16) as i64;
+            self.block_in_column(&columns[i % 16], &world_x, &world_y)
+        });
+        self.place_features(&mut blocks, &base_pos_x, &base_pos_y);
+        Chunk::new(blocks)
+    }
+    fn biome(&self, world_x: &i64) -> Option<Biome> {
+        Some(self.biomes.biome(world_x))
+    }
+    fn surface_height(&self, world_x: &i64) -> Option<i64> {
+        Some(self.get_height(world_x))
     }
 }
 impl WorldGenerator {
-    pub fn new(seed: u32) -> Self {
+    pub fn new(seed: u32, terrain: TerrainConfig, structures: Vec<Structure>) -> Self {
         WorldGenerator {
             gen: noise::Simplex::new(seed),
+            terrain,
+            biomes: BiomeMap::new(seed),
+            underground: Underground::new(seed),
+            features: FeaturePlacer::new(seed, structures),
+        }
+    }
+    /// Stamps the part of every nearby feature that overlaps the chunk
+    fn place_features(&self, blocks: &mut [Block; 16 * 16], base_pos_x: &i64, base_pos_y: &i64) {
+        for (cell, anchor_x) in self.features.anchors(*base_pos_x, base_pos_x + 15) {
+            let column = self.get_column(&anchor_x);
+            if column.height < self.terrain.sea_level {
+                continue;
+            }
+            let surface = self.block_in_column(&column, &anchor_x, &column.height);
+            let Some(placements) = self.features.feature(cell, column.biome, surface) else {
+                continue;
+            };
+
+            for placement in placements {
+                let x = anchor_x + placement.dx - base_pos_x;
+                let y = column.height + 1 + placement.dy - base_pos_y;
+                if !(0..16).contains(&x) || !(0..16).contains(&y) {
+                    continue;
+                }
+                let block = &mut blocks[(x + y * 16) as usize];
+                if placement.replace || *block == Block::AIR {
+                    *block = placement.block;
+                }
+            }
+        }
+    }
+    fn get_column(&self, world_x: &i64) -> Column {
+        Column {
+            height: self.get_height(world_x),
+            biome: self.biomes.biome(world_x),
+            beach_jitter: self.noise1d(world_x, 3., 1.),
+        }
+    }
+    fn block_in_column(&self, column: &Column, world_x: &i64, world_y: &i64) -> Block {
+        let terrain = &self.terrain;
+        if world_y > &column.height {
+            return match (world_y.cmp(&terrain.sea_level), column.biome) {
+                (Ordering::Greater, _) => Block::AIR,
+                (Ordering::Equal, Biome::Tundra) => Block::ICE,
+                (Ordering::Equal, _) => Block::WATER_EDGE,
+                (Ordering::Less, _) => Block::WATER,
+            };
         }
+
+        let params = column.biome.params();
+        let depth = column.height - world_y;
+        let beach = world_y - column.beach_jitter < terrain.beach_level
+            && !matches!(column.biome, Biome::Tundra | Biome::Mountains);
+        let surface = if column.height > terrain.snow_line {
+            Block::SNOW
+        } else {
+            params.surface
+        };
+
+        let block = match depth {
+            d if beach && d < terrain.beach_sand_depth => Block::SAND,
+            d if beach && d < terrain.beach_depth => Block::SAND_STONE,
+            d if d < params.surface_depth => surface,
+            d if d < params.surface_depth + params.subsurface_depth => params.subsurface,
+            _ => Block::STONE,
+        };
+        self.underground.carve(world_x, world_y, depth, block)
     }
     fn get_height(&self, world_x: &i64) -> i64 {
-        ((self.gen.get([*world_x as f64 / 256., 0.]) + 0.5) * 120. - 25.) as i64
-            + self.noise1d(world_x, 1.5, 0.)
+        let (offset, scale) = self.biomes.blended_height(world_x);
+        let noise = self.terrain.sample(&self.gen, world_x);
+        (self.terrain.base_height + offset + noise * self.terrain.height_scale * scale) as i64
+            + self.noise1d(world_x, self.terrain.roughness, 0.)
     }
     fn noise1d(&self, x: &i64, amplitude: f64, s: f64) -> i64 {
         (self.gen.get([s, *x as f64]) * amplitude) as i64
     }
 }
 
-pub struct FlatWorldGenerator {}
+/// A horizontal band of a single block within a flat world
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FlatLayer {
+    pub block: Block,
+    pub height: u32,
+}
+
+/// Generates a world made of horizontal layers
+///
+/// Layers are stacked upwards starting at `base`, everything below `base` is
+/// filled with the bottom layer and everything above the top layer is air.
+pub struct FlatWorldGenerator {
+    base: i64,
+    layers: Vec<FlatLayer>,
+}
+impl Generator for FlatWorldGenerator {
+    fn gen_block(&self, _world_x: &i64, world_y: &i64) -> Block {
+        if world_y < &self.base {
+            return self.layers.first().map_or(Block::AIR, |layer| layer.block);
+        }
+
+        let mut top = self.base;
+        for layer in &self.layers {
+            top += layer.height as i64;
+            if world_y < &top {
+                return layer.block;
+            }
+        }
+        Block::AIR
+    }
+    fn surface_height(&self, _world_x: &i64) -> Option<i64> {
+        let height: i64 = self.layers.iter().map(|layer| layer.height as i64).sum();
+        Some(self.base + height - 1)
+    }
+}
+impl FlatWorldGenerator {
+    pub fn new(base: i64, layers: Vec<FlatLayer>) -> Self {
+        Self { base, layers }
+    }
+}
+impl Default for FlatWorldGenerator {
+    fn default() -> Self {
+        Self::new(
+            -1,
+            vec![
+                FlatLayer {
+                    block: Block::STONE,
+                    height: 1,
+                },
+                FlatLayer {
+                    block: Block::DIRT,
+                    height: 3,
+                },
+                FlatLayer {
+                    block: Block::GRASS_BLOCK,
+                    height: 1,
+                },
+            ],
+        )
+    }
+}
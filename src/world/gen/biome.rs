@@ -0,0 +1,184 @@
+//! Biomes decide the surface blocks, height and features of the terrain
+//!
+//! The world is a side view so biomes only change along x. Three low frequency
+//! noise fields pick the biome of a column: continentalness decides between
+//! ocean, land and mountains while temperature and humidity split the land.
+
+use crate::blocks::Block;
+use macroquad::prelude::Color;
+use noise::{NoiseFn, Simplex};
+use serde::{Deserialize, Serialize};
+
+/// How far apart biome samples are taken when blending heights
+const BLEND_STEP: i64 = 16;
+/// Number of samples taken on each side of a column when blending heights
+const BLEND_SAMPLES: i64 = 4;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Biome {
+    Ocean,
+    Plains,
+    Forest,
+    Desert,
+    Tundra,
+    Mountains,
+}
+
+/// Things placed on top of the terrain once it is generated
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Feature {
+    Trees,
+    Cacti,
+}
+
+/// Everything that makes one biome look different from another
+pub struct BiomeParams {
+    /// Top most block of the terrain
+    pub surface: Block,
+    pub surface_depth: i64,
+    /// Block between the surface and stone
+    pub subsurface: Block,
+    pub subsurface_depth: i64,
+    /// Average terrain height relative to the base height
+    pub height_offset: f64,
+    /// How strongly the height noise shapes the terrain
+    pub height_scale: f64,
+    pub features: &'static [Feature],
+    /// Color of the biome in the debug overlay
+    pub debug_color: Color,
+}
+
+const OCEAN: BiomeParams = BiomeParams {
+    surface: Block::SAND,
+    surface_depth: 4,
+    subsurface: Block::SAND_STONE,
+    subsurface_depth: 5,
+    height_offset: -10.,
+    height_scale: 0.25,
+    features: &[],
+    debug_color: Color::new(0.1, 0.2, 0.9, 1.),
+};
+const PLAINS: BiomeParams = BiomeParams {
+    surface: Block::GRASS_BLOCK,
+    surface_depth: 3,
+    subsurface: Block::DIRT,
+    subsurface_depth: 5,
+    height_offset: 25.,
+    height_scale: 0.15,
+    features: &[Feature::Trees],
+    debug_color: Color::new(0.5, 0.9, 0.3, 1.),
+};
+const FOREST: BiomeParams = BiomeParams {
+    surface: Block::GRASS_BLOCK,
+    surface_depth: 3,
+    subsurface: Block::DIRT,
+    subsurface_depth: 6,
+    height_offset: 30.,
+    height_scale: 0.25,
+    features: &[Feature::Trees],
+    debug_color: Color::new(0.1, 0.5, 0.1, 1.),
+};
+const DESERT: BiomeParams = BiomeParams {
+    surface: Block::SAND,
+    surface_depth: 4,
+    subsurface: Block::SAND_STONE,
+    subsurface_depth: 8,
+    height_offset: 22.,
+    height_scale: 0.12,
+    features: &[Feature::Cacti],
+    debug_color: Color::new(0.9, 0.8, 0.3, 1.),
+};
+const TUNDRA: BiomeParams = BiomeParams {
+    surface: Block::SNOW,
+    surface_depth: 2,
+    subsurface: Block::DIRT,
+    subsurface_depth: 5,
+    height_offset: 30.,
+    height_scale: 0.2,
+    features: &[],
+    debug_color: Color::new(0.9, 0.9, 1., 1.),
+};
+const MOUNTAINS: BiomeParams = BiomeParams {
+    surface: Block::STONE,
+    surface_depth: 1,
+    subsurface: Block::STONE,
+    subsurface_depth: 0,
+    height_offset: 70.,
+    height_scale: 0.8,
+    features: &[],
+    debug_color: Color::new(0.5, 0.5, 0.5, 1.),
+};
+
+impl Biome {
+    pub const ALL: [Biome; 6] = [
+        Biome::Ocean,
+        Biome::Plains,
+        Biome::Forest,
+        Biome::Desert,
+        Biome::Tundra,
+        Biome::Mountains,
+    ];
+
+    pub fn params(&self) -> &'static BiomeParams {
+        match self {
+            Biome::Ocean => &OCEAN,
+            Biome::Plains => &PLAINS,
+            Biome::Forest => &FOREST,
+            Biome::Desert => &DESERT,
+            Biome::Tundra => &TUNDRA,
+            Biome::Mountains => &MOUNTAINS,
+        }
+    }
+}
+
+/// Noise fields used to pick the biome of every column
+pub struct BiomeMap {
+    continentalness: Simplex,
+    temperature: Simplex,
+    humidity: Simplex,
+}
+impl BiomeMap {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            continentalness: Simplex::new(seed.wrapping_add(1)),
+            temperature: Simplex::new(seed.wrapping_add(2)),
+            humidity: Simplex::new(seed.wrapping_add(3)),
+        }
+    }
+    /// Returns the biome a column belongs to
+    pub fn biome(&self, world_x: &i64) -> Biome {
+        let x = *world_x as f64;
+        let continentalness = self.continentalness.get([x / 1024., 0.5]);
+        if continentalness < -0.3 {
+            return Biome::Ocean;
+        }
+        if continentalness > 0.35 {
+            return Biome::Mountains;
+        }
+
+        let temperature = self.temperature.get([x / 2048., 0.5]);
+        let humidity = self.humidity.get([x / 1536., 0.5]);
+        match (temperature, humidity) {
+            (t, _) if t < -0.25 => Biome::Tundra,
+            (t, h) if t > 0.25 && h < 0. => Biome::Desert,
+            (_, h) if h > 0.15 => Biome::Forest,
+            _ => Biome::Plains,
+        }
+    }
+    /// Returns the height offset and scale of a column averaged with the
+    /// biomes around it so the terrain blends smoothly across biome borders
+    pub fn blended_height(&self, world_x: &i64) -> (f64, f64) {
+        let mut offset = 0.;
+        let mut scale = 0.;
+        let mut total_weight = 0.;
+        for i in -BLEND_SAMPLES..=BLEND_SAMPLES {
+            // Closer samples count more
+            let weight = (BLEND_SAMPLES + 1 - i.abs()) as f64;
+            let params = self.biome(&(world_x + i * BLEND_STEP)).params();
+            offset += params.height_offset * weight;
+            scale += params.height_scale * weight;
+            total_weight += weight;
+        }
+        (offset / total_weight, scale / total_weight)
+    }
+}
@@ -0,0 +1,227 @@
+//! Trees, cacti and prefab structures placed on top of the terrain
+//!
+//! Features are anchored to columns picked by hashing the seed with the
+//! column, never by generation order. A chunk looks at every anchor close
+//! enough to reach into it and stamps the overlapping part, so a feature that
+//! crosses a chunk or region border comes out whole no matter which side is
+//! generated first.
+
+use super::biome::{Biome, Feature};
+use crate::blocks::Block;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// Folder prefab structures are loaded from
+pub const STRUCTURES_DIR: &str = "assets/structures";
+/// Furthest a feature may extend sideways from its anchor column
+pub const MAX_REACH: i64 = 8;
+/// The world is split into cells of this many columns holding at most one feature each
+const CELL_WIDTH: i64 = 8;
+
+/* Chances out of 1000 per cell */
+const TREE_CHANCE: u64 = 450;
+const CACTUS_CHANCE: u64 = 200;
+
+/// A single block of a feature relative to the block just above the surface
+#[derive(Clone, Copy)]
+pub struct Placement {
+    pub dx: i64,
+    pub dy: i64,
+    pub block: Block,
+    /// Whether the block replaces terrain or only fills air
+    pub replace: bool,
+}
+
+/// Prefab structure as written in `assets/structures/*.json`, copied into the
+/// world metadata when a world is created
+///
+/// ```json
+/// {
+///     "name": "Well",
+///     "biomes": ["Plains", "Desert"],
+///     "chance": 3,
+///     "palette": { "S": "Stone", "W": "Water", ".": "Air" },
+///     "rows": ["S.S", "SWS"],
+///     "anchor": [1, 1]
+/// }
+/// ```
+/// Rows are listed top first, characters missing from the palette leave the
+/// terrain untouched. The `anchor` cell, counted from the left and the bottom,
+/// is placed just above the surface. `chance` is out of 1000 per cell.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StructureDef {
+    pub name: String,
+    pub biomes: Vec<Biome>,
+    pub chance: u64,
+    pub palette: BTreeMap<char, Block>,
+    pub rows: Vec<String>,
+    pub anchor: (i64, i64),
+}
+impl StructureDef {
+    /// Loads every `.json` structure within a folder, sorted by file name so
+    /// placement does not depend on the order the file system lists them in
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let dir = dir.as_ref();
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .with_context(|| format!("could not read {}", dir.display()))?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+
+        paths
+            .iter()
+            .map(|path| {
+                fs::read_to_string(path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|json| Ok(serde_json::from_str(&json)?))
+                    .with_context(|| format!("could not load structure {}", path.display()))
+            })
+            .collect()
+    }
+}
+
+pub struct Structure {
+    pub name: String,
+    biomes: Vec<Biome>,
+    chance: u64,
+    placements: Vec<Placement>,
+}
+impl Structure {
+    pub fn from_json(json: &str) -> Result<Self> {
+        Self::from_def(&serde_json::from_str(json)?)
+    }
+    pub fn from_def(def: &StructureDef) -> Result<Self> {
+        let (anchor_x, anchor_y) = def.anchor;
+        let height = def.rows.len() as i64;
+
+        let mut placements = Vec::new();
+        for (row, line) in def.rows.iter().enumerate() {
+            for (column, symbol) in line.chars().enumerate() {
+                let Some(block) = def.palette.get(&symbol) else {
+                    continue;
+                };
+                let dx = column as i64 - anchor_x;
+                if dx.abs() > MAX_REACH {
+                    bail!(
+                        "structure {:?} reaches further than {MAX_REACH} blocks from its anchor",
+                        def.name
+                    );
+                }
+                placements.push(Placement {
+                    dx,
+                    dy: height - 1 - row as i64 - anchor_y,
+                    block: *block,
+                    replace: true,
+                });
+            }
+        }
+
+        Ok(Self {
+            name: def.name.clone(),
+            biomes: def.biomes.clone(),
+            chance: def.chance,
+            placements,
+        })
+    }
+}
+
+/// Picks which features grow where
+pub struct FeaturePlacer {
+    seed: u64,
+    structures: Vec<Structure>,
+}
+impl FeaturePlacer {
+    pub fn new(seed: u32, structures: Vec<Structure>) -> Self {
+        Self {
+            seed: seed as u64,
+            structures,
+        }
+    }
+    /// Returns the anchor columns of every cell that could reach into `min_x..=max_x`,
+    /// always in the same order
+    pub fn anchors(&self, min_x: i64, max_x: i64) -> impl Iterator<Item = (i64, i64)> + '_ {
+        let first = (min_x - MAX_REACH).div_euclid(CELL_WIDTH);
+        let last = (max_x + MAX_REACH).div_euclid(CELL_WIDTH);
+        (first..=last).map(move |cell| {
+            let offset = self.hash(cell, 0) % CELL_WIDTH as u64;
+            (cell, cell * CELL_WIDTH + offset as i64)
+        })
+    }
+    /// Returns the blocks of the feature growing in a cell, if any
+    pub fn feature(&self, cell: i64, biome: Biome, surface: Block) -> Option<Vec<Placement>> {
+        let params = biome.params();
+        for (salt, feature) in (1..).zip(params.features) {
+            let chance = match feature {
+                Feature::Trees => TREE_CHANCE,
+                Feature::Cacti => CACTUS_CHANCE,
+            };
+            if self.hash(cell, salt) % 1000 >= chance {
+                continue;
+            }
+            let shape = self.hash(cell, salt + 100);
+            match feature {
+                Feature::Trees if surface == Block::GRASS_BLOCK => return Some(tree(shape)),
+                Feature::Cacti if surface == Block::SAND => return Some(cactus(shape)),
+                _ => {}
+            }
+        }
+
+        for (salt, structure) in (200..).zip(&self.structures) {
+            if structure.biomes.contains(&biome) && self.hash(cell, salt) % 1000 < structure.chance
+            {
+                return Some(structure.placements.clone());
+            }
+        }
+        None
+    }
+    /// Mixes the seed, a cell and a salt into a well spread number
+    fn hash(&self, cell: i64, salt: u64) -> u64 {
+        let mut z = self.seed ^ (cell as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ salt << 48;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Oak tree with a trunk of 4 to 7 blocks and a round crown
+fn tree(shape: u64) -> Vec<Placement> {
+    let trunk = 4 + (shape % 4) as i64;
+    let radius = 2 + ((shape >> 8) % 2) as i64;
+
+    let mut placements: Vec<_> = (0..trunk)
+        .map(|dy| Placement {
+            dx: 0,
+            dy,
+            block: Block::OAK_WOOD,
+            replace: false,
+        })
+        .collect();
+    for dx in -radius..=radius {
+        for dy in -radius..=radius {
+            if dx * dx + dy * dy <= radius * radius + 1 {
+                placements.push(Placement {
+                    dx,
+                    dy: trunk + dy,
+                    block: Block::OAK_LEAVE,
+                    replace: false,
+                });
+            }
+        }
+    }
+    placements
+}
+
+/// Cactus column of 2 to 4 blocks
+fn cactus(shape: u64) -> Vec<Placement> {
+    let height = 2 + (shape % 3) as i64;
+    (0..height)
+        .map(|dy| Placement {
+            dx: 0,
+            dy,
+            block: Block::CACTUS,
+            replace: false,
+        })
+        .collect()
+}
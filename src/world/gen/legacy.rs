@@ -0,0 +1,54 @@
+//! Terrain of worlds created before the generator was versioned
+//!
+//! Chunks nobody changed are never saved and get generated again when needed,
+//! so worlds keep the generator they were created with or newly generated
+//! chunks would not line up with the saved ones.
+
+use super::Generator;
+use crate::blocks::Block;
+use noise::{self, NoiseFn};
+
+/// Version 1 of [`WorldGenerator`](super::WorldGenerator), a single noise
+/// height map without biomes, caves or features
+pub struct LegacyWorldGenerator {
+    gen: noise::Simplex,
+}
+impl Generator for LegacyWorldGenerator {
+    fn gen_block(&self, world_x: &i64, world_y: &i64) -> Block {
+        match self.get_height(world_x) {
+            h if &h >= world_y => {
+                let delta = h - world_y;
+
+                match (world_y - self.noise1d(world_x, 3., 1.), delta) {
+                    (y, d) if d < 4 && y < 54 => Block::SAND,
+                    (y, d) if d < 9 && y < 54 => Block::SAND_STONE,
+                    (_, d) if d < 3 => Block::GRASS_BLOCK,
+                    (_, d) if d < 8 => Block::DIRT,
+                    (_, _) => Block::STONE,
+                }
+            }
+            _ => match world_y {
+                51i64..=i64::MAX => Block::AIR,
+                50 => Block::WATER_EDGE,
+                i64::MIN..=49i64 => Block::WATER,
+            },
+        }
+    }
+    fn surface_height(&self, world_x: &i64) -> Option<i64> {
+        Some(self.get_height(world_x))
+    }
+}
+impl LegacyWorldGenerator {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            gen: noise::Simplex::new(seed),
+        }
+    }
+    fn get_height(&self, world_x: &i64) -> i64 {
+        ((self.gen.get([*world_x as f64 / 256., 0.]) + 0.5) * 120. - 25.) as i64
+            + self.noise1d(world_x, 1.5, 0.)
+    }
+    fn noise1d(&self, x: &i64, amplitude: f64, s: f64) -> i64 {
+        (self.gen.get([s, *x as f64]) * amplitude) as i64
+    }
+}
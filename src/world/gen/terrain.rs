@@ -0,0 +1,127 @@
+//! Configurable height map of [`WorldGenerator`](super::WorldGenerator)
+//!
+//! The settings are stored in the world metadata so designers can tweak the
+//! terrain in `world.json` and a world always reloads with the same shape.
+
+use anyhow::{ensure, Result};
+use noise::{NoiseFn, Simplex};
+use serde::{Deserialize, Serialize};
+
+/// How octaves of noise are combined
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TerrainShape {
+    /// Fractal brownian motion, rolling hills
+    Fbm,
+    /// Inverted absolute noise, sharp ridges and valleys
+    Ridged,
+}
+
+/// Settings of the terrain height and the levels derived from it
+///
+/// The defaults match the height curve of
+/// [`LegacyWorldGenerator`](super::legacy::LegacyWorldGenerator) before biomes
+/// raise and stretch it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TerrainConfig {
+    pub shape: TerrainShape,
+    /// Number of noise layers added together
+    pub octaves: u32,
+    /// Frequency multiplier between octaves
+    pub lacunarity: f64,
+    /// Amplitude multiplier between octaves
+    pub persistence: f64,
+    /// Width in blocks of the largest hills
+    pub wavelength: f64,
+    /// Lowest height of the terrain before biomes raise it
+    pub base_height: f64,
+    /// Height noise is multiplied by this and the biomes height scale
+    pub height_scale: f64,
+    /// Random bumps added on top of the height in blocks
+    pub roughness: f64,
+    /// Height of the water surface
+    pub sea_level: i64,
+    /// Terrain below this height near water turns into a beach
+    pub beach_level: i64,
+    /// How many blocks of sand a beach has before sandstone starts
+    pub beach_sand_depth: i64,
+    /// How many blocks down a beach goes in total
+    pub beach_depth: i64,
+    /// Terrain above this height is covered in snow
+    pub snow_line: i64,
+}
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            shape: TerrainShape::Fbm,
+            octaves: 1,
+            lacunarity: 2.,
+            persistence: 0.5,
+            wavelength: 256.,
+            base_height: 35.,
+            height_scale: 120.,
+            roughness: 1.5,
+            sea_level: 50,
+            beach_level: 54,
+            beach_sand_depth: 4,
+            beach_depth: 9,
+            snow_line: 150,
+        }
+    }
+}
+impl TerrainConfig {
+    /// Checks the settings make a usable height map, they come from `world.json`
+    /// and may have been edited by hand
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            (1..=16).contains(&self.octaves),
+            "terrain octaves is {} but must be between 1 and 16",
+            self.octaves
+        );
+        for (name, value) in [
+            ("wavelength", self.wavelength),
+            ("lacunarity", self.lacunarity),
+            ("persistence", self.persistence),
+        ] {
+            ensure!(
+                value.is_finite() && value > 0.,
+                "terrain {name} is {value} but must be above 0"
+            );
+        }
+        for (name, value) in [
+            ("base_height", self.base_height),
+            ("height_scale", self.height_scale),
+            ("roughness", self.roughness),
+        ] {
+            ensure!(value.is_finite(), "terrain {name} is {value}");
+        }
+        ensure!(
+            (0..=self.beach_depth).contains(&self.beach_sand_depth),
+            "terrain beach_sand_depth is {} but must be between 0 and beach_depth {}",
+            self.beach_sand_depth,
+            self.beach_depth
+        );
+        Ok(())
+    }
+    /// Samples the layered height noise of a column, roughly within -1 and 1
+    pub fn sample(&self, noise: &Simplex, world_x: &i64) -> f64 {
+        let mut frequency = 1. / self.wavelength;
+        let mut amplitude = 1.;
+        let mut total = 0.;
+        let mut max = 0.;
+        for octave in 0..self.octaves.max(1) {
+            // Every octave reads its own row of the noise so they do not line up
+            let value = noise.get([*world_x as f64 * frequency, octave as f64 * 10.]);
+            total += amplitude
+                * match self.shape {
+                    TerrainShape::Fbm => value,
+                    TerrainShape::Ridged => 1. - 2. * value.abs(),
+                };
+            max += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.persistence;
+        }
+        total / max
+    }
+}
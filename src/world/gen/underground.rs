@@ -0,0 +1,102 @@
+//! Caves, underground lakes and ore veins carved out of the terrain
+//!
+//! Every decision is a pure function of the block position and the seed so a
+//! chunk comes out the same no matter which chunks were generated before it.
+
+use crate::blocks::Block;
+use noise::{NoiseFn, Simplex};
+
+/// Caves never get closer to the surface than this
+const CAVE_MIN_DEPTH: i64 = 10;
+/// Noise values closer to zero than this become tunnels
+const TUNNEL_WIDTH: f64 = 0.06;
+/// Noise values above this become large air pockets
+const POCKET_THRESHOLD: f64 = 0.55;
+/// Pockets below this height are flooded
+const LAKE_LEVEL: i64 = -20;
+
+/// Where an ore shows up and how common it is
+struct OreParams {
+    block: Block,
+    /// Depth below the surface where the ore is most common
+    peak_depth: i64,
+    /// How far from the peak depth the ore still appears
+    spread: i64,
+    /// Share of the noise range that turns into ore at the peak depth
+    richness: f64,
+}
+
+const ORES: [OreParams; 4] = [
+    OreParams {
+        block: Block::DIAMOND_ORE,
+        peak_depth: 250,
+        spread: 80,
+        richness: 0.26,
+    },
+    OreParams {
+        block: Block::GOLD_ORE,
+        peak_depth: 140,
+        spread: 60,
+        richness: 0.32,
+    },
+    OreParams {
+        block: Block::IRON_ORE,
+        peak_depth: 60,
+        spread: 50,
+        richness: 0.42,
+    },
+    OreParams {
+        block: Block::COAL_ORE,
+        peak_depth: 20,
+        spread: 40,
+        richness: 0.5,
+    },
+];
+
+pub struct Underground {
+    tunnels: Simplex,
+    pockets: Simplex,
+    ores: Simplex,
+}
+impl Underground {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            tunnels: Simplex::new(seed.wrapping_add(10)),
+            pockets: Simplex::new(seed.wrapping_add(11)),
+            ores: Simplex::new(seed.wrapping_add(12)),
+        }
+    }
+    /// Returns what a solid block of terrain turns into once caves and ores
+    /// are added, `depth` is how far below the surface it is
+    pub fn carve(&self, world_x: &i64, world_y: &i64, depth: i64, block: Block) -> Block {
+        let (x, y) = (*world_x as f64, *world_y as f64);
+
+        if depth >= CAVE_MIN_DEPTH {
+            // Stretched sideways so tunnels run more horizontal than vertical
+            let tunnel = self.tunnels.get([x / 48., y / 24.]).abs() < TUNNEL_WIDTH;
+            let pocket = self.pockets.get([x / 80., y / 60.]) > POCKET_THRESHOLD;
+            match world_y {
+                y if pocket && y < &LAKE_LEVEL => return Block::WATER,
+                &LAKE_LEVEL if pocket => return Block::WATER_EDGE,
+                _ if tunnel || pocket => return Block::AIR,
+                _ => {}
+            }
+        }
+
+        if block != Block::STONE {
+            return block;
+        }
+        for (i, ore) in ORES.iter().enumerate() {
+            let closeness = 1. - (depth - ore.peak_depth).abs() as f64 / ore.spread as f64;
+            if closeness <= 0. {
+                continue;
+            }
+            // Every ore samples its own slice of the noise so veins do not overlap
+            let vein = self.ores.get([x / 6., y / 6. + i as f64 * 1000.]);
+            if vein > 1. - closeness * ore.richness {
+                return ore.block;
+            }
+        }
+        block
+    }
+}
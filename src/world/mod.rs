@@ -3,37 +3,44 @@ pub mod chunks;
 use blocks::Block;
 use chunks::Chunk;
 pub mod gen;
+pub mod region_file;
+pub mod registry;
+pub mod save;
 use gen::Generator;
 
-use super::ChunkMesh;
-use anyhow::Result;
+use super::{ChunkMesh, LightMap, MeshType, Quad};
+use anyhow::{ensure, Context, Result};
 use chrono::{DateTime, Local};
 
-/* Serialization */
-use bincode::{deserialize_from, serialize_into};
-use serde::{Deserialize, Serialize};
-use serde_with::serde_as;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
-use std::{fs, path::PathBuf};
-
-#[serde_as]
-#[derive(Serialize, Deserialize)]
 pub struct Region {
     pub region_x: i32,
     pub region_y: i32,
-    #[serde(skip_serializing, default = "Local::now", skip_deserializing)]
     pub last_used: DateTime<Local>,
-    #[serde_as(as = "[_; 16*16]")]
     pub chunks: [Option<Chunk>; 16 * 16],
-    #[serde_as(as = "[_; 16*16]")]
-    #[serde(skip_serializing, default = "default_chunk_meshes", skip_deserializing)]
     pub chunk_meshes: [Option<ChunkMesh>; 16 * 16],
+    pub light_maps: [Option<LightMap>; 16 * 16],
+    /// Set when a block shown by the mesh or light map of a chunk changed,
+    /// both stay in use until they are rebuilt
+    pub stale_meshes: [bool; 16 * 16],
+    /// Set when a block changed since the region was last saved
+    pub dirty: bool,
+    /// Set when the region file could not be read nor moved aside, the region
+    /// is never saved so the file is left as it was
+    pub read_only: bool,
 }
 fn default_chunk_meshes() -> [Option<ChunkMesh>; 16 * 16] {
     [const { None }; 16 * 16]
 }
+fn default_light_maps() -> [Option<LightMap>; 16 * 16] {
+    [const { None }; 16 * 16]
+}
 impl Region {
-    pub fn get_chunk(&mut self, gen: &impl Generator, x: &u8, y: &u8) -> &Chunk {
+    pub fn get_chunk(&mut self, gen: &dyn Generator, x: &u8, y: &u8) -> &Chunk {
         assert!(x < &16 && y < &16, "That is outside this region");
 
         self.last_used = Local::now();
@@ -46,22 +53,72 @@ impl Region {
         self.chunks[index].as_mut().unwrap().last_used = Local::now();
         self.chunks[index].as_ref().unwrap()
     }
-    pub fn get_chunk_mesh(&mut self, gen: &impl Generator, x: &u8, y: &u8) -> &ChunkMesh {
+    pub fn get_chunk_mesh(
+        &mut self,
+        gen: &dyn Generator,
+        x: &u8,
+        y: &u8,
+        mesh_type: MeshType,
+    ) -> &ChunkMesh {
         assert!(x < &16 && y < &16, "That is outside this region");
 
         self.last_used = Local::now();
         let index = (x + y * 16) as usize;
 
-        if self.chunk_meshes[index].is_none() {
-            let mesh = ChunkMesh::greedy_mesh(self.get_chunk(gen, x, y));
+        if self.chunk_meshes[index].is_none() || self.stale_meshes[index] {
+            let mesh = ChunkMesh::build(self.get_chunk(gen, x, y), mesh_type);
             self.chunk_meshes[index] = Some(mesh);
+            self.light_maps[index] = None;
+            self.stale_meshes[index] = false;
         }
 
         self.chunks[index].as_mut().unwrap().last_used = Local::now();
         self.chunk_meshes[index].as_ref().unwrap()
     }
-    pub fn get_region_path(region_x: &i32, region_y: &i32) -> PathBuf {
-        PathBuf::from(format!("world_file/regions/{region_x}.{region_y}.rf"))
+    /// Rough number of bytes the region takes up in memory including its meshes
+    pub fn memory_usage(&self) -> usize {
+        let quads: usize = self
+            .chunk_meshes
+            .iter()
+            .flatten()
+            .map(|mesh| mesh.mesh.capacity())
+            .sum();
+        let chunks: usize = self
+            .chunks
+            .iter()
+            .flatten()
+            .map(|chunk| chunk.memory_usage() - std::mem::size_of::<Chunk>())
+            .sum();
+        std::mem::size_of::<Self>() + chunks + quads * std::mem::size_of::<Quad>()
+    }
+    /// Drops the meshes and light maps of chunks not used since `idle_since`,
+    /// unmodified ones are dropped entirely since they can be generated again
+    ///
+    /// Returns how many chunks were dropped
+    pub fn unload_idle_chunks(&mut self, idle_since: &DateTime<Local>) -> usize {
+        let mut unloaded = 0;
+        for index in 0..16 * 16 {
+            let Some(chunk) = self.chunks[index].as_mut() else {
+                continue;
+            };
+            if &chunk.last_used >= idle_since {
+                continue;
+            }
+            if !chunk.modified {
+                self.chunks[index] = None;
+                unloaded += 1;
+            } else if self.chunk_meshes[index].is_some() || self.light_maps[index].is_some() {
+                chunk.compact();
+            }
+            self.chunk_meshes[index] = None;
+            self.light_maps[index] = None;
+            self.stale_meshes[index] = false;
+        }
+        unloaded
+    }
+    /// Returns the file a region is stored in within a worlds region folder
+    pub fn get_region_path(regions_dir: &Path, region_x: &i32, region_y: &i32) -> PathBuf {
+        regions_dir.join(format!("{region_x}.{region_y}.rf"))
     }
     pub fn new_empty(region_x: &i32, region_y: &i32) -> Self {
         Self {
@@ -70,16 +127,20 @@ impl Region {
             last_used: Local::now(),
             chunks: [const { None }; 16 * 16],
             chunk_meshes: default_chunk_meshes(),
+            light_maps: default_light_maps(),
+            stale_meshes: [false; 16 * 16],
+            dirty: false,
+            read_only: false,
         }
     }
     pub fn get_block(
         &mut self,
-        gen: &impl Generator,
+        gen: &dyn Generator,
         chunk_x: &u8,
         chunk_y: &u8,
         x: &u8,
         y: &u8,
-    ) -> &Block {
+    ) -> Block {
         assert!(
             chunk_x < &16 && chunk_y < &16,
             "That is outside this region"
@@ -102,26 +163,114 @@ impl Region {
         self.chunks[chunk_index].as_mut().unwrap().last_used = Local::now();
 
         // Retrieve the block from the chunk
-        &self.chunks[chunk_index].as_ref().unwrap().blocks[(x + 16 * y) as usize]
+        self.chunks[chunk_index].as_ref().unwrap().get(x, y)
+    }
+    /// Returns a block only if its chunk is already loaded, never generates anything
+    pub fn peek_block(&self, chunk_x: &u8, chunk_y: &u8, x: &u8, y: &u8) -> Option<Block> {
+        assert!(
+            chunk_x < &16 && chunk_y < &16,
+            "That is outside this region"
+        );
+        assert!(x < &16 && y < &16, "That is outside the chunk");
+
+        let chunk_index = (chunk_x + chunk_y * 16) as usize;
+        self.chunks[chunk_index]
+            .as_ref()
+            .map(|chunk| chunk.get(x, y))
+    }
+    /// Overwrites a block within a loaded chunk and marks its mesh stale
+    ///
+    /// Returns the previous block, or `None` if the chunk is not loaded
+    pub fn set_block(
+        &mut self,
+        chunk_x: &u8,
+        chunk_y: &u8,
+        x: &u8,
+        y: &u8,
+        block: Block,
+    ) -> Option<Block> {
+        assert!(
+            chunk_x < &16 && chunk_y < &16,
+            "That is outside this region"
+        );
+        assert!(x < &16 && y < &16, "That is outside the chunk");
+
+        let chunk_index = (chunk_x + chunk_y * 16) as usize;
+        let chunk = self.chunks[chunk_index].as_mut()?;
+        let previous = chunk.set(x, y, block);
+        if previous == block {
+            return Some(previous);
+        }
+
+        self.last_used = Local::now();
+        chunk.last_used = Local::now();
+        self.stale_meshes[chunk_index] = true;
+        self.dirty = true;
+        Some(previous)
+    }
+    /// Marks the mesh and light map of a chunk stale so they get rebuilt, they
+    /// are kept until then
+    pub fn invalidate_chunk_mesh(&mut self, chunk_x: &u8, chunk_y: &u8) {
+        assert!(
+            chunk_x < &16 && chunk_y < &16,
+            "That is outside this region"
+        );
+        self.stale_meshes[(chunk_x + chunk_y * 16) as usize] = true;
     }
 }
 impl Region {
-    /// Loads region from save file if it does not work then create empty one
-    pub fn load(region_x: &i32, region_y: &i32) -> Self {
-        let path = Self::get_region_path(&region_x, &region_y);
-
-        fs::File::open(&path)
-            .map_err(|_| ())
-            .and_then(|file| deserialize_from(file).map_err(|_| ()))
-            .unwrap_or_else(|_| Self::new_empty(region_x, region_y))
+    /// Loads a region from its file, regions that were never saved start out empty
+    pub fn load(regions_dir: &Path, region_x: &i32, region_y: &i32) -> Result<Self> {
+        let path = Self::get_region_path(regions_dir, region_x, region_y);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::new_empty(region_x, region_y))
+            }
+            Err(e) => return Err(e).with_context(|| format!("could not read {}", path.display())),
+        };
+        region_file::decode(&bytes, region_x, region_y)
+            .with_context(|| format!("could not read {}", path.display()))
+    }
+    /// Moves an unreadable region file out of the way so saving the region
+    /// again does not overwrite it
+    ///
+    /// Returns where the file was moved to, named after the time so earlier
+    /// unreadable files are kept too
+    pub fn set_aside(regions_dir: &Path, region_x: &i32, region_y: &i32) -> Result<PathBuf> {
+        let path = Self::get_region_path(regions_dir, region_x, region_y);
+        let stamp = Local::now().format("%Y%m%d-%H%M%S");
+        let mut aside = path.with_extension(format!("rf.{stamp}.unreadable"));
+        for count in 1.. {
+            if !aside.exists() {
+                break;
+            }
+            aside = path.with_extension(format!("rf.{stamp}-{count}.unreadable"));
+        }
+        fs::rename(&path, &aside)
+            .with_context(|| format!("could not move {} aside", path.display()))?;
+        Ok(aside)
     }
     /// Saves region into save file
-    pub fn save(&self) -> Result<()> {
-        let path = Self::get_region_path(&self.region_x, &self.region_y);
-        fs::File::create(&path).and_then(|file| {
-            serialize_into(file, self)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
-        })?;
+    ///
+    /// The file is written next to the old one first and then swapped in, so a
+    /// failed save leaves the previous version intact
+    pub fn save(&self, regions_dir: &Path) -> Result<()> {
+        let path = Self::get_region_path(regions_dir, &self.region_x, &self.region_y);
+        ensure!(
+            !self.read_only,
+            "region {} {} is read only, {} could not be read",
+            self.region_x,
+            self.region_y,
+            path.display()
+        );
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let partial = path.with_extension("rf.partial");
+        fs::write(&partial, region_file::encode(self))
+            .and_then(|()| fs::rename(&partial, &path))
+            .with_context(|| format!("could not save region to {}", path.display()))?;
         Ok(())
     }
 }
@@ -0,0 +1,230 @@
+//! Binary layout of region files
+//!
+//! All numbers are little endian. A file starts with a fixed size header:
+//! ```text
+//! offset  size     field
+//! 0       4        magic, the bytes `SERF`
+//! 4       2        format version, see [`REGION_FORMAT_VERSION`]
+//! 6       4        region x (i32)
+//! 10      4        region y (i32)
+//! 14      256 * 8  chunk table, an offset (u32) from the start of the file and
+//!                  a length (u32) per chunk, row by row from the bottom. A
+//!                  length of 0 means the chunk is not stored
+//! 2062    4        CRC32 of everything above
+//! ```
+//! followed by one record per stored chunk:
+//! ```text
+//! 0       1        compression, 0 for raw and 1 for run length encoded
+//! 1       4        CRC32 of the payload
+//! 5       ...      payload
+//! ```
+//! Raw payloads hold the 256 block ids (u16) of the chunk row by row from the
+//! bottom. Run length encoded payloads hold runs of a length minus one (u8)
+//! followed by a block id (u16) that together cover the 256 blocks. Each chunk
+//! is written with whichever is smaller.
+//!
+//! Files without the magic are from before this format and hold the bincode
+//! encoded region, they are read with [`decode`] and written in this format
+//! the next time they are saved.
+
+use super::{blocks::Block, chunks::Chunk, Region};
+use anyhow::{bail, ensure, Context, Result};
+use serde::Deserialize;
+use serde_with::serde_as;
+
+/// Identifies a region file
+pub const MAGIC: [u8; 4] = *b"SERF";
+/// Version of the region file layout written by this build
+pub const REGION_FORMAT_VERSION: u16 = 1;
+
+const TABLE_OFFSET: usize = 14;
+const HEADER_SIZE: usize = TABLE_OFFSET + 16 * 16 * 8 + 4;
+const RECORD_HEADER_SIZE: usize = 5;
+
+const RAW: u8 = 0;
+const RLE: u8 = 1;
+
+/// Encodes the modified chunks of a region, the rest are generated again when loaded
+pub fn encode(region: &Region) -> Vec<u8> {
+    let mut bytes = vec![0; HEADER_SIZE];
+    bytes[0..4].copy_from_slice(&MAGIC);
+    bytes[4..6].copy_from_slice(&REGION_FORMAT_VERSION.to_le_bytes());
+    bytes[6..10].copy_from_slice(&region.region_x.to_le_bytes());
+    bytes[10..14].copy_from_slice(&region.region_y.to_le_bytes());
+
+    for (index, chunk) in region.chunks.iter().enumerate() {
+        let Some(chunk) = chunk.as_ref().filter(|chunk| chunk.modified) else {
+            continue;
+        };
+        let blocks = chunk.blocks();
+        let (raw, rle) = (encode_raw(&blocks), encode_rle(&blocks));
+        let (compression, payload) = if rle.len() < raw.len() {
+            (RLE, rle)
+        } else {
+            (RAW, raw)
+        };
+
+        let offset = bytes.len() as u32;
+        let length = (RECORD_HEADER_SIZE + payload.len()) as u32;
+        let entry = TABLE_OFFSET + index * 8;
+        bytes[entry..entry + 4].copy_from_slice(&offset.to_le_bytes());
+        bytes[entry + 4..entry + 8].copy_from_slice(&length.to_le_bytes());
+
+        bytes.push(compression);
+        bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+    }
+
+    let checksum = crc32(&bytes[..HEADER_SIZE - 4]);
+    bytes[HEADER_SIZE - 4..HEADER_SIZE].copy_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+/// Decodes a region file, files from before this format are migrated and
+/// marked dirty so they get rewritten
+pub fn decode(bytes: &[u8], region_x: &i32, region_y: &i32) -> Result<Region> {
+    let region = if bytes.starts_with(&MAGIC) {
+        decode_current(bytes)?
+    } else {
+        decode_legacy(bytes).context("not a region file")?
+    };
+    ensure!(
+        (region.region_x, region.region_y) == (*region_x, *region_y),
+        "file holds region {} {}",
+        region.region_x,
+        region.region_y
+    );
+    Ok(region)
+}
+
+fn decode_current(bytes: &[u8]) -> Result<Region> {
+    ensure!(bytes.len() >= HEADER_SIZE, "header is cut off");
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version > REGION_FORMAT_VERSION {
+        bail!("format version {version} is newer than the supported {REGION_FORMAT_VERSION}");
+    }
+    ensure!(
+        crc32(&bytes[..HEADER_SIZE - 4]) == read_u32(bytes, HEADER_SIZE - 4),
+        "header checksum does not match"
+    );
+
+    let region_x = read_u32(bytes, 6) as i32;
+    let region_y = read_u32(bytes, 10) as i32;
+    let mut region = Region::new_empty(&region_x, &region_y);
+    for index in 0..16 * 16 {
+        let offset = read_u32(bytes, TABLE_OFFSET + index * 8) as usize;
+        let length = read_u32(bytes, TABLE_OFFSET + index * 8 + 4) as usize;
+        if length == 0 {
+            continue;
+        }
+        let record = offset
+            .checked_add(length)
+            .and_then(|end| bytes.get(offset..end))
+            .filter(|record| record.len() >= RECORD_HEADER_SIZE)
+            .with_context(|| format!("chunk {index} lies outside the file"))?;
+        let blocks = decode_record(record).with_context(|| format!("chunk {index} is damaged"))?;
+        let mut chunk = Chunk::new(blocks);
+        chunk.modified = true;
+        region.chunks[index] = Some(chunk);
+    }
+    Ok(region)
+}
+
+fn decode_record(record: &[u8]) -> Result<[Block; 16 * 16]> {
+    let payload = &record[RECORD_HEADER_SIZE..];
+    ensure!(
+        crc32(payload) == read_u32(record, 1),
+        "checksum does not match"
+    );
+    let ids: Vec<u16> = match record[0] {
+        RAW => payload
+            .chunks_exact(2)
+            .map(|id| u16::from_le_bytes([id[0], id[1]]))
+            .collect(),
+        RLE => payload
+            .chunks_exact(3)
+            .flat_map(|run| {
+                let id = u16::from_le_bytes([run[1], run[2]]);
+                std::iter::repeat_n(id, run[0] as usize + 1)
+            })
+            .collect(),
+        compression => bail!("unknown compression {compression}"),
+    };
+    ensure!(
+        ids.len() == 16 * 16,
+        "holds {} blocks instead of 256",
+        ids.len()
+    );
+    Ok(std::array::from_fn(|i| Block(ids[i])))
+}
+
+/// Layout regions were saved with before region files had a header
+#[serde_as]
+#[derive(Deserialize)]
+struct LegacyRegion {
+    region_x: i32,
+    region_y: i32,
+    #[serde_as(as = "[_; 16*16]")]
+    chunks: [Option<Chunk>; 16 * 16],
+}
+
+fn decode_legacy(bytes: &[u8]) -> Result<Region> {
+    let legacy: LegacyRegion = bincode::deserialize(bytes)?;
+    let mut region = Region::new_empty(&legacy.region_x, &legacy.region_y);
+    region.chunks = legacy.chunks;
+    region.dirty = true;
+    Ok(region)
+}
+
+fn encode_raw(blocks: &[Block; 16 * 16]) -> Vec<u8> {
+    blocks
+        .iter()
+        .flat_map(|block| block.0.to_le_bytes())
+        .collect()
+}
+
+fn encode_rle(blocks: &[Block; 16 * 16]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < blocks.len() {
+        let run = blocks[i..]
+            .iter()
+            .take(256)
+            .take_while(|block| block == &&blocks[i])
+            .count();
+        bytes.push((run - 1) as u8);
+        bytes.extend_from_slice(&blocks[i].0.to_le_bytes());
+        i += run;
+    }
+    bytes
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+/// CRC32 with the polynomial used by zip and png
+pub fn crc32(bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !bytes.iter().fold(!0, |crc, byte| {
+        TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
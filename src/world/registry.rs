@@ -0,0 +1,206 @@
+//! Data driven block definitions loaded from a json file
+//!
+//! The engine only ever stores a [`Block`] id per cell, everything else about
+//! a block is looked up here. The built in registry is embedded from
+//! `assets/blocks.json`, a replacement can be loaded at startup with
+//! [`BlockRegistry::load`] followed by [`BlockRegistry::install`].
+
+use super::blocks::{Block, BlockClass};
+use anyhow::{anyhow, bail, Context, Result};
+use macroquad::prelude::{Color, PURPLE};
+use serde::{Deserialize, Deserializer};
+use std::{collections::HashMap, fs, path::Path, sync::OnceLock};
+
+static REGISTRY: OnceLock<BlockRegistry> = OnceLock::new();
+
+/// Block definitions bundled with the engine
+const BUILTIN_BLOCKS: &str = include_str!("../../assets/blocks.json");
+
+/// Blocks the engine refers to directly, their ids must never change or old saves break
+const ENGINE_BLOCKS: [(Block, &str); 18] = [
+    (Block::AIR, "Air"),
+    (Block::GRASS_BLOCK, "GrassBlock"),
+    (Block::DIRT, "Dirt"),
+    (Block::STONE, "Stone"),
+    (Block::WATER, "Water"),
+    (Block::WATER_EDGE, "WaterEdge"),
+    (Block::SAND, "Sand"),
+    (Block::SAND_STONE, "SandStone"),
+    (Block::OAK_WOOD, "OakWood"),
+    (Block::OAK_LEAVE, "OakLeave"),
+    (Block::FIRE, "Fire"),
+    (Block::SNOW, "Snow"),
+    (Block::ICE, "Ice"),
+    (Block::COAL_ORE, "CoalOre"),
+    (Block::IRON_ORE, "IronOre"),
+    (Block::GOLD_ORE, "GoldOre"),
+    (Block::DIAMOND_ORE, "DiamondOre"),
+    (Block::CACTUS, "Cactus"),
+];
+
+/// Everything known about a single block type
+#[derive(Deserialize, Clone, Debug)]
+pub struct BlockDefinition {
+    pub id: u16,
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_hex_color")]
+    pub color: Color,
+    /// How the simulation moves the block
+    #[serde(default = "default_class")]
+    pub class: BlockClass,
+    /// Relative weight, heavier blocks sink through lighter non static ones
+    #[serde(default = "default_density")]
+    pub density: u8,
+    /// Whether the block is treated as solid for collisions
+    #[serde(default = "default_solid")]
+    pub solid: bool,
+    /// Chance per tick of catching fire while next to a burning block
+    #[serde(default)]
+    pub flammability: f32,
+    /// Average number of ticks the block lasts while burning or next to fire
+    #[serde(default)]
+    pub burn_time: u32,
+    /// Index of the blocks tile within the texture sheet
+    #[serde(default)]
+    pub texture: Option<u16>,
+    /// Light level the block gives off, capped at [`MAX_LIGHT`](crate::MAX_LIGHT)
+    #[serde(default)]
+    pub light: u8,
+}
+fn default_class() -> BlockClass {
+    BlockClass::Static
+}
+fn default_density() -> u8 {
+    u8::MAX
+}
+fn default_solid() -> bool {
+    true
+}
+
+/// Parses colors written as `#rrggbb` or `#rrggbbaa`
+fn deserialize_hex_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    parse_hex_color(&text).map_err(serde::de::Error::custom)
+}
+fn parse_hex_color(text: &str) -> Result<Color> {
+    let hex = text
+        .strip_prefix('#')
+        .ok_or_else(|| anyhow!("color {text:?} must start with '#'"))?;
+    let value = u32::from_str_radix(hex, 16).with_context(|| format!("invalid color {text:?}"))?;
+    match hex.len() {
+        6 => Ok(Color::from_hex(value)),
+        8 => Ok(Color::from_rgba(
+            (value >> 24) as u8,
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        )),
+        _ => bail!("color {text:?} must have 6 or 8 hex digits"),
+    }
+}
+
+/// Lookup table from block ids to their definitions
+pub struct BlockRegistry {
+    definitions: Vec<Option<BlockDefinition>>,
+    names: HashMap<String, Block>,
+    unknown: BlockDefinition,
+}
+impl BlockRegistry {
+    /// Builds a registry from a json array of block definitions
+    pub fn from_json(json: &str) -> Result<Self> {
+        let definitions: Vec<BlockDefinition> =
+            serde_json::from_str(json).context("invalid block definitions")?;
+        Self::new(definitions)
+    }
+    /// Loads a registry from a json file on disk
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        Self::from_json(&json).with_context(|| format!("could not load {}", path.display()))
+    }
+    /// Registry bundled with the engine
+    pub fn builtin() -> Self {
+        Self::from_json(BUILTIN_BLOCKS).expect("Builtin block definitions are invalid")
+    }
+    /// Validates definitions and builds the lookup tables
+    pub fn new(definitions: Vec<BlockDefinition>) -> Result<Self> {
+        let mut table: Vec<Option<BlockDefinition>> = Vec::new();
+        let mut names = HashMap::new();
+
+        for definition in definitions {
+            let index = definition.id as usize;
+            if table.len() <= index {
+                table.resize(index + 1, None);
+            }
+            if table[index].is_some() {
+                bail!("block id {} is defined twice", definition.id);
+            }
+            // Used as a probability by the fire simulation
+            if !(0.0..=1.0).contains(&definition.flammability) {
+                bail!(
+                    "block {:?} has flammability {} but it must be between 0 and 1",
+                    definition.name,
+                    definition.flammability
+                );
+            }
+            if names
+                .insert(definition.name.clone(), Block(definition.id))
+                .is_some()
+            {
+                bail!("block name {:?} is defined twice", definition.name);
+            }
+            table[index] = Some(definition);
+        }
+
+        for (block, name) in ENGINE_BLOCKS {
+            if names.get(name) != Some(&block) {
+                bail!("block {name:?} must be defined with id {}", block.0);
+            }
+        }
+
+        Ok(Self {
+            definitions: table,
+            names,
+            unknown: BlockDefinition {
+                id: u16::MAX,
+                name: "Unknown".to_string(),
+                color: PURPLE,
+                class: BlockClass::Static,
+                density: u8::MAX,
+                solid: true,
+                flammability: 0.,
+                burn_time: 0,
+                texture: None,
+                light: 0,
+            },
+        })
+    }
+    /// Makes this the registry used by every block, can only happen once and
+    /// before any block has been looked up
+    pub fn install(self) -> Result<()> {
+        REGISTRY
+            .set(self)
+            .map_err(|_| anyhow!("a block registry is already in use"))
+    }
+    /// Returns the definition of a block, unknown ids get a purple placeholder
+    pub fn get(&self, block: &Block) -> &BlockDefinition {
+        self.definitions
+            .get(block.0 as usize)
+            .and_then(Option::as_ref)
+            .unwrap_or(&self.unknown)
+    }
+    /// Finds a block by its name
+    pub fn by_name(&self, name: &str) -> Option<Block> {
+        self.names.get(name).copied()
+    }
+    /// Iterates over every defined block
+    pub fn blocks(&self) -> impl Iterator<Item = &BlockDefinition> {
+        self.definitions.iter().flatten()
+    }
+}
+
+/// Returns the registry in use, falling back to the builtin one
+pub fn registry() -> &'static BlockRegistry {
+    REGISTRY.get_or_init(BlockRegistry::builtin)
+}
@@ -0,0 +1,139 @@
+//! On disk layout of a world
+//!
+//! Every world lives in its own folder:
+//! ```text
+//! worlds/<name>/
+//!     world.json      metadata, see [`WorldMeta`]
+//!     regions/        one `<x>.<y>.rf` file per saved region
+//! ```
+
+use super::gen::GeneratorKind;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Folder that holds every world
+pub const WORLDS_DIR: &str = "worlds";
+/// Version of the world folder layout written by this build
+///
+/// Version 2 stores regions in the format of [`region_file`](super::region_file),
+/// version 1 region files are read as well and rewritten when they are saved
+pub const WORLD_FORMAT_VERSION: u32 = 2;
+
+const META_FILE: &str = "world.json";
+const REGIONS_DIR: &str = "regions";
+
+/// Contents of `world.json`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WorldMeta {
+    pub name: String,
+    pub seed: u32,
+    /// Worlds written before the generator was stored keep the terrain of version 1
+    #[serde(default = "GeneratorKind::legacy")]
+    pub generator: GeneratorKind,
+    /// [`fingerprint`](super::gen::fingerprint) of the generator, recorded the
+    /// first time the world is opened
+    #[serde(default)]
+    pub generator_check: Option<u32>,
+    #[serde(default = "Local::now")]
+    pub created: DateTime<Local>,
+    #[serde(default = "Local::now")]
+    pub last_played: DateTime<Local>,
+    /// Worlds written before versioning are treated as version 1
+    #[serde(default = "default_version")]
+    pub version: u32,
+}
+fn default_version() -> u32 {
+    1
+}
+impl WorldMeta {
+    /// Metadata for a brand new world
+    pub fn new(name: &str, seed: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            seed,
+            generator: GeneratorKind::default(),
+            generator_check: None,
+            created: Local::now(),
+            last_played: Local::now(),
+            version: WORLD_FORMAT_VERSION,
+        }
+    }
+}
+
+/// A world folder on disk together with its metadata
+pub struct WorldSave {
+    dir: PathBuf,
+    pub meta: WorldMeta,
+}
+impl WorldSave {
+    /// Returns the folder a world with the given name is stored in
+    pub fn path_for(name: &str) -> PathBuf {
+        Path::new(WORLDS_DIR).join(name)
+    }
+    /// Opens an existing world folder
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        let path = dir.join(META_FILE);
+        let file =
+            fs::File::open(&path).with_context(|| format!("could not open {}", path.display()))?;
+        let mut meta: WorldMeta = serde_json::from_reader(file)
+            .with_context(|| format!("could not read {}", path.display()))?;
+
+        if meta.version > WORLD_FORMAT_VERSION {
+            bail!(
+                "world {:?} uses format version {} but only {} is supported",
+                meta.name,
+                meta.version,
+                WORLD_FORMAT_VERSION
+            );
+        }
+        meta.last_played = Local::now();
+        // Older worlds are upgraded as their regions get saved, builds that only
+        // know the old format must not open them afterwards
+        meta.version = WORLD_FORMAT_VERSION;
+
+        Ok(Self { dir, meta })
+    }
+    /// Creates a new world folder and writes its metadata
+    pub fn create(dir: impl Into<PathBuf>, meta: WorldMeta) -> Result<Self> {
+        let world = Self {
+            dir: dir.into(),
+            meta,
+        };
+        fs::create_dir_all(world.regions_dir())
+            .with_context(|| format!("could not create {}", world.dir.display()))?;
+        world.save_meta()?;
+        Ok(world)
+    }
+    /// Opens a world folder or creates it with the metadata `meta` returns if there is none
+    pub fn open_or_create(
+        dir: impl Into<PathBuf>,
+        meta: impl FnOnce() -> Result<WorldMeta>,
+    ) -> Result<Self> {
+        let dir = dir.into();
+        if dir.join(META_FILE).exists() {
+            Self::open(dir)
+        } else {
+            Self::create(dir, meta()?)
+        }
+    }
+    /// Writes `world.json`
+    pub fn save_meta(&self) -> Result<()> {
+        let path = self.dir.join(META_FILE);
+        let json = serde_json::to_string_pretty(&self.meta)?;
+        fs::write(&path, json).with_context(|| format!("could not write {}", path.display()))
+    }
+    /// Folder holding the world
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+    /// Folder holding the region files of the world
+    pub fn regions_dir(&self) -> PathBuf {
+        self.dir.join(REGIONS_DIR)
+    }
+}
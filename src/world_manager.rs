@@ -1,70 +1,633 @@
-use super::{gen::WorldGenerator, ChunkMesh, Region};
+use super::{
+    gen::{self, biome::Biome, Generator},
+    save::WorldSave,
+    ChunkMesh, ChunkWindow, LightMap, MeshType,
+};
 use crate::blocks::Block;
-use chrono::{Duration, Local};
-use std::collections::HashMap;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Local};
+use jobs::{JobKind, JobPool, JobResult};
+use rand::{rngs::StdRng, SeedableRng};
+use regions::RegionCache;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+pub mod jobs;
+mod regions;
+pub use regions::{RegionStats, DEFAULT_REGION_BUDGET};
+mod simulation;
+
+/// Inclusive rectangle of world cords
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WorldRect {
+    pub min_x: i64,
+    pub min_y: i64,
+    pub max_x: i64,
+    pub max_y: i64,
+}
+impl WorldRect {
+    /// Creates a rectangle spanning both corners in any order
+    pub fn new(x1: i64, y1: i64, x2: i64, y2: i64) -> Self {
+        Self {
+            min_x: x1.min(x2),
+            min_y: y1.min(y2),
+            max_x: x1.max(x2),
+            max_y: y1.max(y2),
+        }
+    }
+    pub fn contains(&self, world_x: &i64, world_y: &i64) -> bool {
+        (self.min_x..=self.max_x).contains(world_x) && (self.min_y..=self.max_y).contains(world_y)
+    }
+    /// Iterates over every block position inside, row by row from the bottom
+    pub fn iter(&self) -> impl Iterator<Item = (i64, i64)> {
+        let (min_x, max_x) = (self.min_x, self.max_x);
+        (self.min_y..=self.max_y).flat_map(move |y| (min_x..=max_x).map(move |x| (x, y)))
+    }
+}
 
 pub struct WorldManager {
-    gen: WorldGenerator,
-    regions: HashMap<(i32, i32), Region>,
+    world: WorldSave,
+    gen: Arc<dyn Generator>,
+    regions: RegionCache,
+    /// World cords of chunks that need to be simulated next tick
+    active_chunks: HashSet<(i64, i64)>,
+    /// World cords of blocks that already moved during the current tick
+    moved_blocks: HashSet<(i64, i64)>,
+    rng: StdRng,
+    tick_count: u64,
+    /// Mesher new chunk meshes are built with
+    mesh_strategy: MeshType,
+    /// Whether meshes darken blocks below the surface and out of the light
+    shading: bool,
+    jobs: JobPool,
+    /// World cords of chunks being generated by a worker
+    pending_chunks: HashSet<(i64, i64)>,
+    /// World cords of chunks being meshed by a worker with the ticket of the
+    /// request, blocks changing under a request give it a new ticket
+    pending_meshes: HashMap<(i64, i64), u64>,
+    next_ticket: u64,
+    /// Center of the area in view, jobs closer to it run first
+    focus: (i64, i64),
+    /// When the view was last updated, regions used since are never evicted
+    view_updated: DateTime<Local>,
 }
 
 impl WorldManager {
-    pub fn new(seed: u32) -> Self {
-        Self {
-            gen: WorldGenerator::new(seed),
-            regions: HashMap::new(),
+    /// Fails if the world needs a generator this build does not have or the
+    /// generator makes different terrain than when the world was created
+    pub fn new(mut world: WorldSave) -> Result<Self> {
+        let seed = world.meta.seed;
+        let gen: Arc<dyn Generator> = world.meta.generator.build(seed)?.into();
+        // Chunks nobody changed are not saved, generating them differently
+        // would not line up with the saved ones
+        let check = gen::fingerprint(gen.as_ref());
+        match world.meta.generator_check {
+            Some(saved) if saved != check => bail!(
+                "world {:?} generates different terrain than when it was created, remove \
+                 generator_check from world.json to open it anyway",
+                world.meta.name
+            ),
+            _ => world.meta.generator_check = Some(check),
         }
+        Ok(Self {
+            jobs: JobPool::new(gen.clone()),
+            gen,
+            regions: RegionCache::new(world.regions_dir(), DEFAULT_REGION_BUDGET),
+            world,
+            active_chunks: HashSet::new(),
+            moved_blocks: HashSet::new(),
+            rng: StdRng::seed_from_u64(seed as u64),
+            tick_count: 0,
+            mesh_strategy: MeshType::default(),
+            shading: true,
+            pending_chunks: HashSet::new(),
+            pending_meshes: HashMap::new(),
+            next_ticket: 0,
+            focus: (0, 0),
+            view_updated: Local::now(),
+        })
     }
 
-    pub fn get_chunk_mesh(
-        &mut self,
-        region_x: &i32,
-        region_y: &i32,
-        regional_chunk_x: &u8,
-        regional_chunk_y: &u8,
-    ) -> &ChunkMesh {
-        if !self.regions.contains_key(&(*region_x, *region_y)) {
-            self.regions
-                .insert((*region_x, *region_y), Region::load(region_x, region_y));
+    /// Loads the regions overlapping `area` and queues generating and meshing
+    /// every chunk inside that is not ready yet, nearest to its center first
+    ///
+    /// Called once per frame before drawing so [`Self::chunk_mesh`] has
+    /// something to return
+    pub fn update_view(&mut self, area: &WorldRect) {
+        self.focus = (
+            area.min_x + (area.max_x - area.min_x) / 2,
+            area.min_y + (area.max_y - area.min_y) / 2,
+        );
+        self.view_updated = Local::now();
+        self.regions.next_frame();
+        self.regions.poll();
+        self.collect_jobs();
+
+        let (min_x, min_y) = conversion::get_chunk_world_cords(&area.min_x, &area.min_y);
+        for origin_y in (min_y..=area.max_y).step_by(16) {
+            for origin_x in (min_x..=area.max_x).step_by(16) {
+                let (region_x, region_y) = conversion::get_region_cords(&origin_x, &origin_y);
+                let (chunk_x, chunk_y) = conversion::get_region_chunk_cords(&origin_x, &origin_y);
+                let Some(region) = self.regions.request((region_x, region_y)) else {
+                    continue;
+                };
+
+                let index = (chunk_x + chunk_y * 16) as usize;
+                if let Some(chunk) = region.chunks[index].as_mut() {
+                    chunk.last_used = Local::now();
+                }
+                if region.chunk_meshes[index].is_none() || region.stale_meshes[index] {
+                    self.request_mesh((origin_x, origin_y));
+                }
+            }
         }
+    }
 
-        let region = self.regions.get_mut(&(*region_x, *region_y)).unwrap();
-        region.get_chunk_mesh(&self.gen, regional_chunk_x, regional_chunk_y)
+    /// Drops queued jobs so the chunks still in view can be requested again
+    /// with fresh priorities, then applies every finished result
+    fn collect_jobs(&mut self) {
+        for job in self.jobs.cancel_pending() {
+            match job {
+                JobKind::Generate { region, chunk } => {
+                    let origin = gen::chunk_origin(&region.0, &region.1, &chunk.0, &chunk.1);
+                    self.pending_chunks.remove(&origin);
+                }
+                JobKind::Mesh { origin, .. } => {
+                    self.pending_meshes.remove(&origin);
+                }
+            }
+        }
+
+        let results: Vec<_> = self.jobs.results().collect();
+        for result in results {
+            match result {
+                JobResult::Generated {
+                    region: (region_x, region_y),
+                    chunk: (chunk_x, chunk_y),
+                    data,
+                } => {
+                    let origin = gen::chunk_origin(&region_x, &region_y, &chunk_x, &chunk_y);
+                    self.pending_chunks.remove(&origin);
+                    // Unloaded regions regenerate the chunk when they come back
+                    if let Some(region) = self.regions.get_mut(&(region_x, region_y)) {
+                        let slot = &mut region.chunks[(chunk_x + chunk_y * 16) as usize];
+                        if slot.is_none() {
+                            *slot = Some(*data);
+                        }
+                    }
+                }
+                JobResult::Meshed {
+                    origin,
+                    ticket,
+                    mesh,
+                    light,
+                } => {
+                    // A block nearby changed since the request when the ticket
+                    // moved on, the mesh is still newer than the one shown but
+                    // stays stale so it is requested again
+                    let current = self.pending_meshes.remove(&origin) == Some(ticket);
+                    let (region_x, region_y) = conversion::get_region_cords(&origin.0, &origin.1);
+                    let (chunk_x, chunk_y) =
+                        conversion::get_region_chunk_cords(&origin.0, &origin.1);
+                    if let Some(region) = self.regions.get_mut(&(region_x, region_y)) {
+                        let index = (chunk_x + chunk_y * 16) as usize;
+                        region.chunk_meshes[index] = Some(mesh);
+                        region.light_maps[index] = light.map(|light| *light);
+                        if current {
+                            region.stale_meshes[index] = false;
+                        }
+                    }
+                }
+            }
+        }
     }
 
-    pub fn clean(&mut self) {
-        let now = Local::now();
-        let keys_to_remove: Vec<_> = self
-            .regions
-            .iter()
-            .filter_map(|(key, region)| {
-                if now - region.last_used > Duration::seconds(2) {
-                    Some(key.clone())
-                } else {
-                    None
+    /// Number of chunks waiting for or being worked on by a worker
+    pub fn get_pending_job_count(&self) -> usize {
+        self.pending_chunks.len() + self.pending_meshes.len()
+    }
+
+    /// Returns the mesh of the chunk at the given world cords, or `None` while
+    /// it is not loaded or still being generated or meshed in the background
+    ///
+    /// After a block change the previous mesh is returned until the new one is done
+    pub fn chunk_mesh(&self, origin_x: &i64, origin_y: &i64) -> Option<&ChunkMesh> {
+        let (region_x, region_y) = conversion::get_region_cords(origin_x, origin_y);
+        let (chunk_x, chunk_y) = conversion::get_region_chunk_cords(origin_x, origin_y);
+        self.regions.get(&(region_x, region_y))?.chunk_meshes[(chunk_x + chunk_y * 16) as usize]
+            .as_ref()
+    }
+
+    /// Queues meshing a chunk once it and every chunk its mesh can see are
+    /// loaded, queueing the generation of the missing ones first
+    fn request_mesh(&mut self, (origin_x, origin_y): (i64, i64)) {
+        if self.pending_meshes.contains_key(&(origin_x, origin_y)) {
+            return;
+        }
+        let (focus_x, focus_y) = self.focus;
+        let priority = (origin_x + 8 - focus_x).unsigned_abs().pow(2)
+            + (origin_y + 8 - focus_y).unsigned_abs().pow(2);
+
+        let mut ready = true;
+        for dy in [-16, 0, 16] {
+            for dx in [-16, 0, 16] {
+                let (x, y) = (origin_x + dx, origin_y + dy);
+                let (region_x, region_y) = conversion::get_region_cords(&x, &y);
+                let (chunk_x, chunk_y) = conversion::get_region_chunk_cords(&x, &y);
+                let Some(region) = self.regions.request((region_x, region_y)) else {
+                    ready = false;
+                    continue;
+                };
+                if region.chunks[(chunk_x + chunk_y * 16) as usize].is_some() {
+                    continue;
                 }
+                ready = false;
+                if self.pending_chunks.insert((x, y)) {
+                    self.jobs.submit(
+                        priority,
+                        JobKind::Generate {
+                            region: (region_x, region_y),
+                            chunk: (chunk_x, chunk_y),
+                        },
+                    );
+                }
+            }
+        }
+        if !ready {
+            return;
+        }
+
+        // Freshly loaded or changed chunks get a chance to settle
+        self.active_chunks.insert((origin_x, origin_y));
+
+        let window = ChunkWindow::new(|x, y| {
+            self.peek_block(&(origin_x + x), &(origin_y + y))
+                .expect("Chunks around a meshed chunk are loaded")
+        })
+        .with_roofs(|x| self.roofed(&(origin_x + x), &(origin_y + 16 + ChunkMesh::MARGIN)));
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        self.pending_meshes.insert((origin_x, origin_y), ticket);
+        self.jobs.submit(
+            priority,
+            JobKind::Mesh {
+                origin: (origin_x, origin_y),
+                ticket,
+                window,
+                mesh_type: self.mesh_strategy,
+                shading: self.shading,
+            },
+        );
+    }
+
+    /// Returns the light level of a block, lighting its chunk first if needed
+    pub fn get_light(&mut self, world_x: &i64, world_y: &i64) -> u8 {
+        let origin = conversion::get_chunk_world_cords(world_x, world_y);
+        let (region_x, region_y) = conversion::get_region_cords(world_x, world_y);
+        let (chunk_x, chunk_y) = conversion::get_region_chunk_cords(world_x, world_y);
+        let (local_x, local_y) = conversion::get_local_chunk_cords(world_x, world_y);
+
+        let index = (chunk_x + chunk_y * 16) as usize;
+        let cached = self
+            .regions
+            .get(&(region_x, region_y))
+            .filter(|region| !region.stale_meshes[index])
+            .and_then(|region| region.light_maps[index].as_ref());
+        match cached {
+            Some(light) => light.get(&local_x, &local_y),
+            None => {
+                let window = self.chunk_window(origin);
+                self.chunk_light(origin, &window).get(&local_x, &local_y)
+            }
+        }
+    }
+
+    /// Returns the light level of a block if its chunk has been lit already,
+    /// possibly from before a block nearby changed
+    pub fn peek_light(&self, world_x: &i64, world_y: &i64) -> Option<u8> {
+        let (region_x, region_y) = conversion::get_region_cords(world_x, world_y);
+        let (chunk_x, chunk_y) = conversion::get_region_chunk_cords(world_x, world_y);
+        let (local_x, local_y) = conversion::get_local_chunk_cords(world_x, world_y);
+        self.regions.get(&(region_x, region_y))?.light_maps[(chunk_x + chunk_y * 16) as usize]
+            .as_ref()
+            .map(|light| light.get(&local_x, &local_y))
+    }
+
+    /// Light map of a chunk, lit from its window and kept until a block nearby changes
+    fn chunk_light(&mut self, (origin_x, origin_y): (i64, i64), window: &ChunkWindow) -> LightMap {
+        let (region_x, region_y) = conversion::get_region_cords(&origin_x, &origin_y);
+        let (chunk_x, chunk_y) = conversion::get_region_chunk_cords(&origin_x, &origin_y);
+        let region = self.regions.load((region_x, region_y));
+        let index = (chunk_x + chunk_y * 16) as usize;
+        // A stale light map is still drawn until the mesh catches up, so it is
+        // replaced without clearing the stale mark
+        if region.stale_meshes[index] || region.light_maps[index].is_none() {
+            region.light_maps[index] = Some(LightMap::compute(window));
+        }
+        region.light_maps[index].clone().unwrap()
+    }
+
+    /// Blocks of a chunk and its surroundings, loading neighbours as needed
+    fn chunk_window(&mut self, (origin_x, origin_y): (i64, i64)) -> ChunkWindow {
+        ChunkWindow::new(|x, y| self.get_block(&(origin_x + x), &(origin_y + y)))
+            .with_surface(|x| {
+                self.gen
+                    .surface_height(&(origin_x + x))
+                    .map(|height| height - origin_y)
             })
-            .collect();
+            .with_roofs(|x| self.roofed(&(origin_x + x), &(origin_y + 16 + ChunkMesh::MARGIN)))
+    }
+
+    /// Whether a loaded solid block sits in a column at or above `world_y`
+    ///
+    /// Scans up until the loaded world ends, skipping chunks made of a single
+    /// non solid block.
+    fn roofed(&self, world_x: &i64, world_y: &i64) -> bool {
+        let (local_x, _) = conversion::get_local_chunk_cords(world_x, world_y);
+        let mut y = *world_y;
+        loop {
+            let (region_x, region_y) = conversion::get_region_cords(world_x, &y);
+            let (chunk_x, chunk_y) = conversion::get_region_chunk_cords(world_x, &y);
+            let Some(chunk) = self
+                .regions
+                .get(&(region_x, region_y))
+                .and_then(|region| region.chunks[(chunk_x + chunk_y * 16) as usize].as_ref())
+            else {
+                return false;
+            };
+            if chunk.uniform().is_none_or(|block| block.is_solid()) {
+                let (_, from) = conversion::get_local_chunk_cords(world_x, &y);
+                if (from..16).any(|local_y| chunk.get(&local_x, &local_y).is_solid()) {
+                    return true;
+                }
+            }
+            y = (y | 0b1111) + 1;
+        }
+    }
+
+    pub fn mesh_strategy(&self) -> MeshType {
+        self.mesh_strategy
+    }
+    /// Switches the mesher and rebuilds every loaded mesh with it
+    pub fn set_mesh_strategy(&mut self, mesh_type: MeshType) {
+        if self.mesh_strategy == mesh_type {
+            return;
+        }
+        self.mesh_strategy = mesh_type;
+        self.invalidate_all_meshes();
+    }
 
-        for key in keys_to_remove {
-            self.regions.remove(&key);
+    pub fn shading(&self) -> bool {
+        self.shading
+    }
+    /// Turns shading and lighting on or off and rebuilds every loaded mesh
+    pub fn set_shading(&mut self, shading: bool) {
+        if self.shading == shading {
+            return;
         }
+        self.shading = shading;
+        self.invalidate_all_meshes();
+    }
+
+    /// Marks every loaded mesh stale, they are drawn until rebuilt
+    fn invalidate_all_meshes(&mut self) {
+        for region in self.regions.values_mut() {
+            region.stale_meshes = [true; 16 * 16];
+        }
+        let origins: Vec<_> = self.pending_meshes.keys().copied().collect();
+        for origin in origins {
+            self.outdate_pending_mesh(&origin);
+        }
+    }
+
+    /// Gives a mesh request a new ticket so its result is known to be outdated
+    /// when it arrives
+    fn outdate_pending_mesh(&mut self, origin: &(i64, i64)) {
+        if let Some(ticket) = self.pending_meshes.get_mut(origin) {
+            *ticket = self.next_ticket;
+            self.next_ticket += 1;
+        }
+    }
+
+    /// Unloads the least recently used regions once they take up more memory
+    /// than the budget, changed ones are saved in the background first
+    ///
+    /// Regions used since the last [`Self::update_view`] stay loaded. Regions that
+    /// fail to save are loaded again and retried later
+    pub fn clean(&mut self) -> Result<()> {
+        self.regions.evict(&self.view_updated)
+    }
+
+    /// Saves every region that has unsaved changes and the world metadata
+    ///
+    /// Regions are saved even if the metadata can not be written
+    pub fn save_all(&mut self) -> Result<()> {
+        let regions = self.regions.save_all();
+        let meta = self.world.save_meta();
+        match (regions, meta) {
+            (Err(e), Err(meta)) => Err(e.context(format!("{meta:#}"))),
+            (regions, meta) => regions.and(meta),
+        }
+    }
+
+    /// Sets how many bytes loaded regions may take up before they are evicted
+    pub fn set_region_budget(&mut self, budget: usize) {
+        self.regions.set_budget(budget);
+    }
+
+    /// Hits, misses and evictions of the region cache
+    pub fn region_stats(&self) -> RegionStats {
+        self.regions.stats()
+    }
+
+    /// Returns the world being managed
+    pub fn world(&self) -> &WorldSave {
+        &self.world
     }
 
     pub fn get_region_count(&self) -> usize {
         self.regions.len()
     }
 
-    pub fn get_block(&mut self, world_x: &i64, world_y: &i64) -> &Block {
+    pub fn get_active_chunk_count(&self) -> usize {
+        self.active_chunks.len()
+    }
+
+    pub fn get_block(&mut self, world_x: &i64, world_y: &i64) -> Block {
+        let (region_x, region_y) = conversion::get_region_cords(world_x, world_y);
+        let (chunk_x, chunk_y) = conversion::get_region_chunk_cords(world_x, world_y);
+        let (local_x, local_y) = conversion::get_local_chunk_cords(world_x, world_y);
+        let region = self.regions.load((region_x, region_y));
+        region.get_block(self.gen.as_ref(), &chunk_x, &chunk_y, &local_x, &local_y)
+    }
+
+    /// Returns the biome of a column, if the world has biomes
+    pub fn get_biome(&self, world_x: &i64) -> Option<Biome> {
+        self.gen.biome(world_x)
+    }
+
+    /// Returns a block only if it is already loaded, never loads or generates anything
+    pub fn peek_block(&self, world_x: &i64, world_y: &i64) -> Option<Block> {
         let (region_x, region_y) = conversion::get_region_cords(world_x, world_y);
-        if !self.regions.contains_key(&(region_x, region_y)) {
-            self.regions
-                .insert((region_x, region_y), Region::load(&region_x, &region_y));
+        let (chunk_x, chunk_y) = conversion::get_region_chunk_cords(world_x, world_y);
+        let (local_x, local_y) = conversion::get_local_chunk_cords(world_x, world_y);
+        self.regions
+            .get(&(region_x, region_y))?
+            .peek_block(&chunk_x, &chunk_y, &local_x, &local_y)
+    }
+
+    /// Places a block, loading or generating its chunk if needed
+    ///
+    /// Returns the block that was there before
+    pub fn set_block(&mut self, world_x: &i64, world_y: &i64, block: Block) -> Block {
+        let previous = self.get_block(world_x, world_y);
+        if previous != block {
+            self.swap_loaded_block(world_x, world_y, block);
+        }
+        previous
+    }
+
+    /// Fills every block inside `area`
+    ///
+    /// Returns the positions that changed together with their previous block
+    pub fn fill_rect(&mut self, area: &WorldRect, block: Block) -> Vec<((i64, i64), Block)> {
+        self.set_blocks(area.iter(), block)
+    }
+
+    /// Fills every block within `radius` of the center
+    ///
+    /// Returns the positions that changed together with their previous block
+    pub fn fill_circle(
+        &mut self,
+        center_x: &i64,
+        center_y: &i64,
+        radius: u32,
+        block: Block,
+    ) -> Vec<((i64, i64), Block)> {
+        let radius = radius as i64;
+        let area = WorldRect::new(
+            center_x - radius,
+            center_y - radius,
+            center_x + radius,
+            center_y + radius,
+        );
+        let (center_x, center_y) = (*center_x, *center_y);
+        let cells = area
+            .iter()
+            .filter(move |(x, y)| (x - center_x).pow(2) + (y - center_y).pow(2) <= radius.pow(2));
+        self.set_blocks(cells, block)
+    }
+
+    /// Swaps every `from` block inside `area` with `to`
+    ///
+    /// Returns the positions that changed together with their previous block
+    pub fn replace(
+        &mut self,
+        from: Block,
+        to: Block,
+        area: &WorldRect,
+    ) -> Vec<((i64, i64), Block)> {
+        let cells: Vec<_> = area
+            .iter()
+            .filter(|(x, y)| self.get_block(x, y) == from)
+            .collect();
+        self.set_blocks(cells, to)
+    }
+
+    fn set_blocks(
+        &mut self,
+        cells: impl IntoIterator<Item = (i64, i64)>,
+        block: Block,
+    ) -> Vec<((i64, i64), Block)> {
+        cells
+            .into_iter()
+            .filter_map(|(x, y)| {
+                let previous = self.set_block(&x, &y, block);
+                (previous != block).then_some(((x, y), previous))
+            })
+            .collect()
+    }
+
+    /// Rebuilds the meshes of every other chunk whose mesh can see this block
+    fn invalidate_meshes_around(&mut self, world_x: &i64, world_y: &i64) {
+        let margin = ChunkMesh::MARGIN;
+        self.invalidate_meshes_in(&WorldRect::new(
+            world_x - margin,
+            world_y - margin,
+            world_x + margin,
+            world_y + margin,
+        ));
+    }
+
+    /// Rebuilds the meshes of the chunks below a block that turned solid or
+    /// non solid with nothing solid above it, as far down as its shadow falls
+    fn invalidate_shadow(&mut self, world_x: &i64, world_y: &i64) {
+        let mut bottom = world_y - 1;
+        while self
+            .peek_block(world_x, &bottom)
+            .is_some_and(|block| !block.is_solid())
+        {
+            bottom -= 1;
+        }
+        let margin = ChunkMesh::MARGIN;
+        self.invalidate_meshes_in(&WorldRect::new(
+            world_x - margin,
+            bottom - margin,
+            world_x + margin,
+            *world_y,
+        ));
+    }
+
+    /// Rebuilds the meshes of every loaded chunk overlapping `area`
+    fn invalidate_meshes_in(&mut self, area: &WorldRect) {
+        let (min_x, min_y) = conversion::get_chunk_world_cords(&area.min_x, &area.min_y);
+        for y in (min_y..=area.max_y).step_by(16) {
+            for x in (min_x..=area.max_x).step_by(16) {
+                let (region_x, region_y) = conversion::get_region_cords(&x, &y);
+                let (chunk_x, chunk_y) = conversion::get_region_chunk_cords(&x, &y);
+                if let Some(region) = self.regions.get_mut(&(region_x, region_y)) {
+                    region.invalidate_chunk_mesh(&chunk_x, &chunk_y);
+                }
+                self.outdate_pending_mesh(&(x, y));
+            }
         }
+    }
+
+    /// Overwrites a loaded block, wakes up the chunks around it and marks every mesh it shows up in stale
+    ///
+    /// Returns the previous block, or `None` if the block is not loaded
+    fn swap_loaded_block(&mut self, world_x: &i64, world_y: &i64, block: Block) -> Option<Block> {
+        let (region_x, region_y) = conversion::get_region_cords(world_x, world_y);
         let (chunk_x, chunk_y) = conversion::get_region_chunk_cords(world_x, world_y);
         let (local_x, local_y) = conversion::get_local_chunk_cords(world_x, world_y);
-        let region = self.regions.get_mut(&(region_x, region_y)).unwrap();
-        region.get_block(&self.gen, &chunk_x, &chunk_y, &local_x, &local_y)
+        let previous = self
+            .regions
+            .get_mut(&(region_x, region_y))?
+            .set_block(&chunk_x, &chunk_y, &local_x, &local_y, block)?;
+
+        if previous != block {
+            self.invalidate_meshes_around(world_x, world_y);
+            // Sunlight below reaches further than the margin
+            if previous.is_solid() != block.is_solid() && !self.roofed(world_x, &(world_y + 1)) {
+                self.invalidate_shadow(world_x, world_y);
+            }
+        }
+        self.wake_around(world_x, world_y);
+        Some(previous)
+    }
+
+    /// Schedules every chunk touching the neighbourhood of a block for simulation
+    fn wake_around(&mut self, world_x: &i64, world_y: &i64) {
+        for dx in [-1, 1] {
+            for dy in [-1, 1] {
+                self.active_chunks.insert(conversion::get_chunk_world_cords(
+                    &(world_x + dx),
+                    &(world_y + dy),
+                ));
+            }
+        }
     }
 }
 
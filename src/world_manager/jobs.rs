@@ -0,0 +1,219 @@
+//! Worker threads that generate and mesh chunks away from the render loop
+//!
+//! Jobs wait in a queue ordered by distance to the camera. Jobs that have not
+//! started yet can be cancelled when the view moves on, finished work is
+//! handed back to the [`WorldManager`](super::WorldManager) through a channel.
+
+use crate::{
+    chunks::Chunk, gen::Generator, ChunkMesh, ChunkWindow, Decorations, LightMap, MeshType,
+};
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+/// Work a thread can do without touching the world
+pub enum JobKind {
+    /// Generates a chunk from scratch
+    Generate { region: (i32, i32), chunk: (u8, u8) },
+    /// Lights and meshes a chunk from a copy of the blocks around it
+    Mesh {
+        origin: (i64, i64),
+        /// Tells apart requests for the same chunk, a result whose ticket is no
+        /// longer expected was overtaken by a block change
+        ticket: u64,
+        window: ChunkWindow,
+        mesh_type: MeshType,
+        shading: bool,
+    },
+}
+
+/// Finished work of a [`JobKind`]
+pub enum JobResult {
+    Generated {
+        region: (i32, i32),
+        chunk: (u8, u8),
+        data: Box<Chunk>,
+    },
+    Meshed {
+        origin: (i64, i64),
+        ticket: u64,
+        mesh: ChunkMesh,
+        light: Option<Box<LightMap>>,
+    },
+}
+
+struct Job {
+    /// Lower runs first, usually the squared distance to the camera
+    priority: u64,
+    /// Keeps jobs of the same priority in the order they were submitted
+    sequence: u64,
+    kind: JobKind,
+}
+impl PartialEq for Job {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Job {}
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Job {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed so the heap pops the lowest priority first
+        (other.priority, other.sequence).cmp(&(self.priority, self.sequence))
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: BinaryHeap<Job>,
+    next_sequence: u64,
+    shutdown: bool,
+}
+
+/// Shared state of the pool and its workers
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+}
+
+/// Fixed set of worker threads with a priority queue of jobs
+pub struct JobPool {
+    shared: Arc<Shared>,
+    results: Receiver<JobResult>,
+    workers: Vec<JoinHandle<()>>,
+}
+impl JobPool {
+    /// Starts one worker per spare core
+    pub fn new(gen: Arc<dyn Generator>) -> Self {
+        let threads = thread::available_parallelism()
+            .map_or(2, |cores| cores.get())
+            .saturating_sub(1)
+            .max(1);
+        Self::with_threads(gen, threads)
+    }
+    pub fn with_threads(gen: Arc<dyn Generator>, threads: usize) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::default()),
+            ready: Condvar::new(),
+        });
+        let (sender, results) = mpsc::channel();
+        let workers = (0..threads)
+            .map(|i| {
+                let shared = shared.clone();
+                let gen = gen.clone();
+                let sender = sender.clone();
+                thread::Builder::new()
+                    .name(format!("chunk worker {i}"))
+                    .spawn(move || work(&shared, gen.as_ref(), &sender))
+                    .expect("Could not start chunk worker")
+            })
+            .collect();
+        Self {
+            shared,
+            results,
+            workers,
+        }
+    }
+    /// Queues a job, lower priorities run first
+    pub fn submit(&self, priority: u64, kind: JobKind) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        let sequence = queue.next_sequence;
+        queue.next_sequence += 1;
+        queue.jobs.push(Job {
+            priority,
+            sequence,
+            kind,
+        });
+        self.shared.ready.notify_one();
+    }
+    /// Drops every job that has not started yet and returns them, jobs already
+    /// running still deliver their result
+    pub fn cancel_pending(&self) -> Vec<JobKind> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        queue.jobs.drain().map(|job| job.kind).collect()
+    }
+    /// Returns every result finished since the last call without blocking
+    pub fn results(&self) -> impl Iterator<Item = JobResult> + '_ {
+        self.results.try_iter()
+    }
+}
+impl Drop for JobPool {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().shutdown = true;
+        self.shared.ready.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Runs jobs until the pool shuts down
+fn work(shared: &Shared, gen: &dyn Generator, results: &Sender<JobResult>) {
+    loop {
+        let job = {
+            let mut queue = shared.queue.lock().unwrap();
+            loop {
+                if queue.shutdown {
+                    return;
+                }
+                if let Some(job) = queue.jobs.pop() {
+                    break job;
+                }
+                queue = shared.ready.wait(queue).unwrap();
+            }
+        };
+
+        let result = match job.kind {
+            JobKind::Generate { region, chunk } => JobResult::Generated {
+                region,
+                chunk,
+                data: Box::new(gen.gen_chunk(&region.0, &region.1, &chunk.0, &chunk.1)),
+            },
+            JobKind::Mesh {
+                origin: (origin_x, origin_y),
+                ticket,
+                window,
+                mesh_type,
+                shading,
+            } => {
+                let (mesh, light) = if shading {
+                    let window = window.with_surface(|x| {
+                        gen.surface_height(&(origin_x + x))
+                            .map(|height| height - origin_y)
+                    });
+                    let light = LightMap::compute(&window);
+                    let mesh = ChunkMesh::build_shaded(
+                        &window,
+                        Some(&light),
+                        mesh_type,
+                        Decorations::builtin(),
+                    );
+                    (mesh, Some(Box::new(light)))
+                } else {
+                    let chunk = Chunk::new(window.chunk_blocks());
+                    (ChunkMesh::build(&chunk, mesh_type), None)
+                };
+                JobResult::Meshed {
+                    origin: (origin_x, origin_y),
+                    ticket,
+                    mesh,
+                    light,
+                }
+            }
+        };
+        // The manager is gone when sending fails, nothing left to do
+        if results.send(result).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! Cache of loaded regions with a thread that reads and writes region files
+//!
+//! Regions stay loaded until the cache grows past its memory budget, then the
+//! least recently used ones are dropped and saved in the background if they
+//! changed. A single thread does all file access so a region that is loaded
+//! again right after being evicted is read only after it was written.
+
+use crate::Region;
+use anyhow::{bail, Error, Result};
+use chrono::{DateTime, Duration, Local};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+/// Memory loaded regions may take up before the least recently used are evicted
+pub const DEFAULT_REGION_BUDGET: usize = 64 << 20;
+
+/// Seconds a chunk may go unused before its mesh is dropped, unmodified
+/// chunks are dropped with it
+const CHUNK_IDLE_SECONDS: i64 = 10;
+
+/// Regions are built by value and take up a few hundred kilobytes each, more
+/// than fits on the default stack of a spawned thread in debug builds
+const IO_STACK_SIZE: usize = 16 << 20;
+
+/// Counters of the region cache, shown in the debug menu
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RegionStats {
+    /// Lookups of a region that was already loaded, counted once per region per frame
+    pub hits: u64,
+    /// Lookups that started reading or creating the region
+    pub misses: u64,
+    /// Regions dropped to stay within the budget
+    pub evictions: u64,
+    /// Unmodified chunks dropped after going unused
+    pub chunk_unloads: u64,
+    /// Estimated bytes taken up by loaded regions
+    pub memory: usize,
+    pub budget: usize,
+    /// Region files waiting to be read or written
+    pub pending_io: usize,
+}
+
+enum Request {
+    Load(i32, i32),
+    Save(Box<Region>),
+}
+
+enum Response {
+    Loaded(Box<Region>),
+    /// The file could not be read and was moved aside, the region starts over.
+    /// When it could not be moved the region starts over read only
+    LoadFailed(Box<Region>, Error),
+    Saved,
+    /// Hands the region back so it is not lost
+    SaveFailed(Box<Region>, Error),
+}
+
+/// Background thread doing region file access in the order it was asked for
+struct RegionIo {
+    requests: Option<Sender<Request>>,
+    responses: Receiver<Response>,
+    thread: Option<JoinHandle<()>>,
+}
+impl RegionIo {
+    fn new(regions_dir: PathBuf) -> Self {
+        let (requests, inbox) = mpsc::channel();
+        let (outbox, responses) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("region io".to_string())
+            .stack_size(IO_STACK_SIZE)
+            .spawn(move || serve(&regions_dir, inbox, outbox))
+            .expect("Could not start region io thread");
+        Self {
+            requests: Some(requests),
+            responses,
+            thread: Some(thread),
+        }
+    }
+    fn send(&self, request: Request) {
+        self.requests
+            .as_ref()
+            .unwrap()
+            .send(request)
+            .expect("Region io thread stopped");
+    }
+}
+impl Drop for RegionIo {
+    /// Finishes every queued save before returning
+    fn drop(&mut self) {
+        self.requests = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Runs region file requests until the cache is dropped
+fn serve(regions_dir: &Path, requests: Receiver<Request>, responses: Sender<Response>) {
+    for request in requests {
+        let response = match request {
+            Request::Load(region_x, region_y) => {
+                match Region::load(regions_dir, &region_x, &region_y) {
+                    Ok(region) => Response::Loaded(Box::new(region)),
+                    Err(e) => {
+                        let mut empty = Region::new_empty(&region_x, &region_y);
+                        let e = match Region::set_aside(regions_dir, &region_x, &region_y) {
+                            Ok(aside) => e.context(format!(
+                                "moved it to {} and started the region over",
+                                aside.display()
+                            )),
+                            Err(move_error) => {
+                                // Saving the empty region would overwrite the file
+                                empty.read_only = true;
+                                move_error.context(format!(
+                                    "{e:#}, changes to the region will not be saved"
+                                ))
+                            }
+                        };
+                        Response::LoadFailed(Box::new(empty), e)
+                    }
+                }
+            }
+            Request::Save(region) => match region.save(regions_dir) {
+                Ok(()) => Response::Saved,
+                Err(e) => Response::SaveFailed(region, e),
+            },
+        };
+        // Nobody is listening anymore, queued saves still have to finish
+        let _ = responses.send(response);
+    }
+}
+
+/// Loaded regions of a world bounded by a memory budget
+pub struct RegionCache {
+    regions_dir: PathBuf,
+    regions: HashMap<(i32, i32), Region>,
+    /// Regions asked from the io thread that did not arrive yet
+    loading: HashSet<(i32, i32)>,
+    /// Regions already counted as a hit this frame
+    hit_this_frame: HashSet<(i32, i32)>,
+    /// Evicted regions the io thread has not written yet
+    saving: usize,
+    /// Saves that failed in the background and were not reported yet
+    errors: Vec<Error>,
+    /// Region files that could not be read and were not reported yet
+    load_errors: Vec<Error>,
+    io: RegionIo,
+    stats: RegionStats,
+}
+impl RegionCache {
+    pub fn new(regions_dir: PathBuf, budget: usize) -> Self {
+        Self {
+            io: RegionIo::new(regions_dir.clone()),
+            regions_dir,
+            regions: HashMap::new(),
+            loading: HashSet::new(),
+            hit_this_frame: HashSet::new(),
+            saving: 0,
+            errors: Vec::new(),
+            load_errors: Vec::new(),
+            stats: RegionStats {
+                budget,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Starts counting hits of a new frame
+    pub fn next_frame(&mut self) {
+        self.hit_this_frame.clear();
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.stats.budget = budget;
+    }
+
+    pub fn stats(&self) -> RegionStats {
+        RegionStats {
+            memory: self.regions.values().map(Region::memory_usage).sum(),
+            pending_io: self.loading.len() + self.saving,
+            ..self.stats
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    /// Returns a region only if it is loaded, does not count towards the stats
+    pub fn get(&self, key: &(i32, i32)) -> Option<&Region> {
+        self.regions.get(key)
+    }
+
+    /// Returns a region only if it is loaded, does not count towards the stats
+    pub fn get_mut(&mut self, key: &(i32, i32)) -> Option<&mut Region> {
+        self.regions.get_mut(key)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Region> {
+        self.regions.values_mut()
+    }
+
+    /// Returns a region if it is loaded, otherwise starts loading it in the background
+    pub fn request(&mut self, key: (i32, i32)) -> Option<&mut Region> {
+        if !self.regions.contains_key(&key) {
+            if self.loading.insert(key) {
+                self.stats.misses += 1;
+                self.io.send(Request::Load(key.0, key.1));
+            }
+            return None;
+        }
+        if self.hit_this_frame.insert(key) {
+            self.stats.hits += 1;
+        }
+        let region = self.regions.get_mut(&key).unwrap();
+        region.last_used = Local::now();
+        Some(region)
+    }
+
+    /// Returns a region, waiting for it to be loaded if needed
+    pub fn load(&mut self, key: (i32, i32)) -> &mut Region {
+        if self.request(key).is_none() {
+            while !self.regions.contains_key(&key) {
+                let response = self.io.responses.recv().expect("Region io thread stopped");
+                self.apply(response);
+            }
+        }
+        self.regions.get_mut(&key).unwrap()
+    }
+
+    /// Takes in every region the io thread finished without waiting
+    pub fn poll(&mut self) {
+        while let Ok(response) = self.io.responses.try_recv() {
+            self.apply(response);
+        }
+    }
+
+    fn apply(&mut self, response: Response) {
+        match response {
+            Response::Loaded(region) => {
+                let key = (region.region_x, region.region_y);
+                self.loading.remove(&key);
+                self.regions.entry(key).or_insert(*region);
+            }
+            Response::LoadFailed(region, e) => {
+                self.load_errors.push(e);
+                self.apply(Response::Loaded(region));
+            }
+            Response::Saved => self.saving -= 1,
+            Response::SaveFailed(region, e) => {
+                self.saving -= 1;
+                self.errors.push(e);
+                // Put back so the changes are saved again on the next eviction
+                let key = (region.region_x, region.region_y);
+                let mut region = *region;
+                region.last_used = Local::now();
+                self.regions.insert(key, region);
+            }
+        }
+    }
+
+    /// Drops the meshes of idle chunks and the least recently used regions
+    /// until the cache fits its budget, changed regions are saved in the background
+    ///
+    /// Regions used since `pinned_since` are kept even over budget. Returns the
+    /// errors of region files that could not be read or saved in the background
+    /// since the last call, regions that failed to save are loaded again and
+    /// retried later
+    pub fn evict(&mut self, pinned_since: &DateTime<Local>) -> Result<()> {
+        self.poll();
+
+        let idle_since = Local::now() - Duration::seconds(CHUNK_IDLE_SECONDS);
+        for region in self.regions.values_mut() {
+            self.stats.chunk_unloads += region.unload_idle_chunks(&idle_since) as u64;
+        }
+
+        let mut memory: usize = self.regions.values().map(Region::memory_usage).sum();
+        if memory > self.stats.budget {
+            let mut candidates: Vec<_> = self
+                .regions
+                .iter()
+                .filter(|(_, region)| &region.last_used < pinned_since)
+                .map(|(key, region)| (region.last_used, *key))
+                .collect();
+            candidates.sort_unstable();
+
+            for (_, key) in candidates {
+                if memory <= self.stats.budget {
+                    break;
+                }
+                let region = self.regions.remove(&key).unwrap();
+                memory -= region.memory_usage();
+                self.stats.evictions += 1;
+                if region.dirty && !region.read_only {
+                    self.saving += 1;
+                    self.io.send(Request::Save(Box::new(region)));
+                }
+            }
+        }
+        self.take_errors()
+    }
+
+    /// Waits for the background saves to finish, then saves every loaded
+    /// region that has unsaved changes
+    pub fn save_all(&mut self) -> Result<()> {
+        while self.saving > 0 {
+            let response = self.io.responses.recv().expect("Region io thread stopped");
+            self.apply(response);
+        }
+        // Regions whose background save failed are back in the cache and retried here
+        self.errors.clear();
+        for region in self
+            .regions
+            .values_mut()
+            .filter(|region| region.dirty && !region.read_only)
+        {
+            match region.save(&self.regions_dir) {
+                Ok(()) => region.dirty = false,
+                Err(e) => self.errors.push(e),
+            }
+        }
+        self.take_errors()
+    }
+
+    /// Reports every error since the last call in one, each on its own line
+    fn take_errors(&mut self) -> Result<()> {
+        let mut errors: Vec<_> = self.load_errors.drain(..).collect();
+        errors.append(&mut self.errors);
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.pop().unwrap()),
+            failed => {
+                let details: Vec<_> = errors.iter().map(|e| format!("{e:#}")).collect();
+                bail!(
+                    "{failed} region files could not be read or saved:\n{}",
+                    details.join("\n")
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Cellular automaton that moves blocks around the loaded world
+//!
+//! Only chunks that changed recently are simulated, a chunk where nothing moved
+//! falls asleep until a neighbouring change wakes it back up.
+
+use super::{conversion, WorldManager};
+use crate::blocks::{Block, BlockClass};
+use rand::Rng;
+
+/// How far along its surface a liquid looks for a lower spot to flow into
+const FLOW_DISTANCE: i64 = 8;
+
+impl WorldManager {
+    /// Advances the simulation by one step
+    pub fn tick(&mut self) {
+        self.tick_count += 1;
+        self.moved_blocks.clear();
+
+        // Lower chunks first so everything falls at most one block per tick
+        let mut chunks: Vec<_> = self.active_chunks.drain().collect();
+        chunks.sort_unstable_by_key(|&(x, y)| (y, x));
+
+        for (chunk_x, chunk_y) in chunks {
+            self.tick_chunk(&chunk_x, &chunk_y);
+        }
+    }
+
+    fn tick_chunk(&mut self, chunk_x: &i64, chunk_y: &i64) {
+        // Alternate the scan direction so piles do not lean to one side
+        let flip = self.tick_count.is_multiple_of(2);
+
+        for local_y in 0..16 {
+            for i in 0..16 {
+                let local_x = if flip { 15 - i } else { i };
+                let world_x = chunk_x + local_x;
+                let world_y = chunk_y + local_y;
+
+                if self.moved_blocks.contains(&(world_x, world_y)) {
+                    continue;
+                }
+                let Some(block) = self.peek_block(&world_x, &world_y) else {
+                    // Chunk got unloaded
+                    return;
+                };
+
+                match block.class() {
+                    BlockClass::Granular => self.update_granular(&world_x, &world_y, block),
+                    BlockClass::Liquid => self.update_liquid(&world_x, &world_y, block),
+                    BlockClass::Static if block == Block::FIRE => {
+                        self.update_fire(&world_x, &world_y)
+                    }
+                    BlockClass::Static | BlockClass::Gas => {}
+                }
+            }
+        }
+    }
+
+    /// Lets a granular block fall straight down or slide off a slope
+    fn update_granular(&mut self, world_x: &i64, world_y: &i64, block: Block) {
+        let below = world_y - 1;
+        if self.try_move(world_x, world_y, world_x, &below, &block) {
+            return;
+        }
+
+        let side = if self.rng.gen_bool(0.5) { 1 } else { -1 };
+        for dx in [side, -side] {
+            if self.try_move(world_x, world_y, &(world_x + dx), &below, &block) {
+                return;
+            }
+        }
+    }
+
+    /// Lets a liquid fall, spread sideways and keeps its surface marked
+    fn update_liquid(&mut self, world_x: &i64, world_y: &i64, block: Block) {
+        let above = self.peek_block(world_x, &(world_y + 1));
+        let covered = above.is_some_and(|above| above.class() == BlockClass::Liquid);
+        // Liquid under a ceiling has no surface either
+        let open = above == Some(Block::AIR);
+
+        // Only the top most liquid cell with air above shows the surface
+        let block = match (block, above) {
+            (Block::WATER, Some(_)) if open => Block::WATER_EDGE,
+            (Block::WATER_EDGE, Some(_)) if !open => Block::WATER,
+            _ => block,
+        };
+        if Some(block) != self.peek_block(world_x, world_y) {
+            self.swap_loaded_block(world_x, world_y, block);
+        }
+
+        let below = world_y - 1;
+        if self.try_move(world_x, world_y, world_x, &below, &block) {
+            return;
+        }
+
+        let side = if self.rng.gen_bool(0.5) { 1 } else { -1 };
+        for dx in [side, -side] {
+            if self.try_move(world_x, world_y, &(world_x + dx), &below, &block) {
+                return;
+            }
+        }
+
+        // Only spread sideways when pushed by liquid above, this lets a
+        // surface level out and then settle instead of wandering forever
+        if covered {
+            for dx in [side, -side] {
+                if self.try_move(world_x, world_y, &(world_x + dx), world_y, &block) {
+                    return;
+                }
+            }
+        } else {
+            // A bump on the surface flows towards a lower spot nearby
+            for dx in [side, -side] {
+                if self.has_drop_towards(world_x, world_y, &dx, &block)
+                    && self.try_move(world_x, world_y, &(world_x + dx), world_y, &block)
+                {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Whether a liquid can flow sideways in direction `dx` and fall down
+    /// within [`FLOW_DISTANCE`] blocks
+    fn has_drop_towards(&self, world_x: &i64, world_y: &i64, dx: &i64, block: &Block) -> bool {
+        for step in 1..=FLOW_DISTANCE {
+            let x = world_x + dx * step;
+            let (Some(level), Some(below)) = (
+                self.peek_block(&x, world_y),
+                self.peek_block(&x, &(world_y - 1)),
+            ) else {
+                return false;
+            };
+            if !block.can_displace(&level) {
+                return false;
+            }
+            if block.can_displace(&below) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Spreads fire to flammable neighbours, burns them away and lets the fire die out
+    fn update_fire(&mut self, world_x: &i64, world_y: &i64) {
+        let neighbours: Vec<_> = (-1..=1)
+            .flat_map(|dx| (-1..=1).map(move |dy| (world_x + dx, world_y + dy)))
+            .filter(|&(x, y)| (x, y) != (*world_x, *world_y))
+            .filter_map(|(x, y)| Some((x, y, self.peek_block(&x, &y)?)))
+            .collect();
+
+        // Put out by any liquid touching it
+        if neighbours
+            .iter()
+            .any(|(_, _, block)| block.class() == BlockClass::Liquid)
+        {
+            self.swap_loaded_block(world_x, world_y, Block::AIR);
+            return;
+        }
+
+        for (x, y, block) in neighbours {
+            let definition = block.definition();
+            if definition.flammability <= 0. {
+                continue;
+            }
+            if self.rng.gen_bool(definition.flammability as f64) {
+                self.swap_loaded_block(&x, &y, Block::FIRE);
+                self.moved_blocks.insert((x, y));
+            } else if self.rng.gen_ratio(1, definition.burn_time.max(1)) {
+                self.swap_loaded_block(&x, &y, Block::AIR);
+            }
+        }
+
+        if self
+            .rng
+            .gen_ratio(1, Block::FIRE.definition().burn_time.max(1))
+        {
+            self.swap_loaded_block(world_x, world_y, Block::AIR);
+        } else {
+            // Keep burning next tick
+            self.active_chunks
+                .insert(conversion::get_chunk_world_cords(world_x, world_y));
+        }
+    }
+
+    /// Swaps `block` into the target cell if it is loaded and can be displaced
+    fn try_move(
+        &mut self,
+        from_x: &i64,
+        from_y: &i64,
+        to_x: &i64,
+        to_y: &i64,
+        block: &Block,
+    ) -> bool {
+        let Some(target) = self.peek_block(to_x, to_y) else {
+            return false;
+        };
+        if !block.can_displace(&target) {
+            return false;
+        }
+
+        self.swap_loaded_block(to_x, to_y, *block);
+        self.swap_loaded_block(from_x, from_y, target);
+        self.moved_blocks.insert((*to_x, *to_y));
+        true
+    }
+}
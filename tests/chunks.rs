@@ -0,0 +1,76 @@
+mod common;
+
+use chrono::Local;
+use common::temp_dir;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sand_engine::{blocks::Block, chunks::Chunk, gen::GeneratorKind, Region};
+
+#[test]
+fn packed_chunks_read_back_what_was_written() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let mut expected = [Block::STONE; 16 * 16];
+    let mut chunk = Chunk::new(expected);
+    // Grows the palette past every bit width and back
+    for round in 0..4000 {
+        let (x, y) = (rng.gen_range(0..16u8), rng.gen_range(0..16u8));
+        let block = Block(rng.gen_range(0..(round / 200 + 2)) % 18);
+        let previous = chunk.set(&x, &y, block);
+        assert_eq!(previous, expected[(x + 16 * y) as usize]);
+        expected[(x + 16 * y) as usize] = block;
+        if round % 500 == 0 {
+            chunk.compact();
+        }
+    }
+    assert_eq!(chunk.blocks(), expected);
+    for (i, block) in expected.iter().enumerate() {
+        assert_eq!(&chunk.get(&((i % 16) as u8), &((i / 16) as u8)), block);
+    }
+}
+
+#[test]
+fn full_palettes_drop_unused_blocks() {
+    let mut chunk = Chunk::new([Block::AIR; 16 * 16]);
+    let mut expected = [Block::AIR; 16 * 16];
+    // Every cell a different block twice over, the palette never gets compacted
+    for id in 1..=512 {
+        let i = id as usize % 256;
+        chunk.set(&((i % 16) as u8), &((i / 16) as u8), Block(id));
+        expected[i] = Block(id);
+        assert_eq!(chunk.get(&((i % 16) as u8), &((i / 16) as u8)), Block(id));
+    }
+    assert_eq!(chunk.blocks(), expected);
+}
+
+#[test]
+fn uniform_chunks_take_up_less_memory() {
+    let uniform = Chunk::new([Block::WATER; 16 * 16]);
+    let mut mixed = uniform.clone();
+    mixed.set(&3, &4, Block::SAND);
+    assert!(uniform.memory_usage() < mixed.memory_usage());
+    assert!(mixed.memory_usage() < 16 * 16 * std::mem::size_of::<Block>());
+
+    mixed.set(&3, &4, Block::WATER);
+    mixed.compact();
+    assert_eq!(mixed.memory_usage(), uniform.memory_usage());
+}
+
+#[test]
+fn only_modified_chunks_are_kept_and_saved() {
+    let gen = GeneratorKind::flat().build(0).unwrap();
+    let mut region = Region::new_empty(&0, &0);
+    region.get_block(gen.as_ref(), &0, &0, &0, &0);
+    region.get_block(gen.as_ref(), &1, &0, &0, &0);
+    region.set_block(&1, &0, &5, &5, Block::FIRE);
+    assert!(!region.chunks[0].as_ref().unwrap().modified);
+    assert!(region.chunks[1].as_ref().unwrap().modified);
+
+    let dir = temp_dir("chunks");
+    region.save(&dir).unwrap();
+    let loaded = Region::load(&dir, &0, &0).unwrap();
+    assert!(loaded.chunks[0].is_none());
+    assert_eq!(loaded.peek_block(&1, &0, &5, &5), Some(Block::FIRE));
+
+    assert_eq!(region.unload_idle_chunks(&Local::now()), 1);
+    assert!(region.chunks[0].is_none());
+    assert_eq!(region.peek_block(&1, &0, &5, &5), Some(Block::FIRE));
+}
@@ -0,0 +1,26 @@
+//! Fixtures shared by the integration tests
+
+// Every test crate compiles this module but only uses some of it
+#![allow(dead_code)]
+
+use sand_engine::{
+    gen::GeneratorKind,
+    save::{WorldMeta, WorldSave},
+    WorldManager,
+};
+use std::{env, fs, path::PathBuf};
+
+/// Fresh empty folder in the temporary directory, unique to the test run
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("sand-engine-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Flat world in a fresh temporary folder, its surface is at y 3
+pub fn flat_world(name: &str) -> WorldManager {
+    let mut meta = WorldMeta::new(name, 0);
+    meta.generator = GeneratorKind::flat();
+    WorldManager::new(WorldSave::create(temp_dir(name), meta).unwrap()).unwrap()
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::flat_world;
+use sand_engine::{blocks::Block, WorldRect};
+
+#[test]
+fn set_block_returns_the_previous_block() {
+    let mut manager = flat_world("set");
+    assert_eq!(manager.set_block(&5, &3, Block::SAND), Block::GRASS_BLOCK);
+    assert_eq!(manager.set_block(&5, &3, Block::SAND), Block::SAND);
+    assert_eq!(manager.get_block(&5, &3), Block::SAND);
+}
+
+#[test]
+fn fill_rect_reports_only_changed_blocks() {
+    let mut manager = flat_world("fill-rect");
+    manager.set_block(&1, &10, Block::STONE);
+    // Crosses chunk borders on both axes
+    let area = WorldRect::new(14, 18, 1, 10);
+    let changed = manager.fill_rect(&area, Block::STONE);
+
+    assert_eq!(changed.len(), 14 * 9 - 1);
+    assert!(changed.iter().all(|(_, previous)| previous == &Block::AIR));
+    assert!(!changed.iter().any(|(cell, _)| cell == &(1, 10)));
+    assert!(area
+        .iter()
+        .all(|(x, y)| manager.get_block(&x, &y) == Block::STONE));
+    assert_eq!(manager.get_block(&0, &10), Block::AIR);
+    assert_eq!(manager.get_block(&15, &10), Block::AIR);
+    assert!(manager.fill_rect(&area, Block::STONE).is_empty());
+}
+
+#[test]
+fn fill_circle_stays_within_the_radius() {
+    let mut manager = flat_world("fill-circle");
+    let changed = manager.fill_circle(&0, &40, 3, Block::SAND);
+
+    // Every cell with x² + y² <= 9
+    assert_eq!(changed.len(), 29);
+    for (x, y) in WorldRect::new(-4, 36, 4, 44).iter() {
+        let inside = x * x + (y - 40) * (y - 40) <= 9;
+        let expected = if inside { Block::SAND } else { Block::AIR };
+        assert_eq!(manager.get_block(&x, &y), expected, "{x} {y}");
+    }
+}
+
+#[test]
+fn replace_only_swaps_matching_blocks() {
+    let mut manager = flat_world("replace");
+    // Grass on top of dirt
+    let area = WorldRect::new(0, 0, 3, 5);
+    let changed = manager.replace(Block::GRASS_BLOCK, Block::SNOW, &area);
+
+    let mut cells: Vec<_> = changed.iter().map(|(cell, _)| *cell).collect();
+    cells.sort();
+    assert_eq!(cells, vec![(0, 3), (1, 3), (2, 3), (3, 3)]);
+    assert!(changed
+        .iter()
+        .all(|(_, previous)| previous == &Block::GRASS_BLOCK));
+    assert_eq!(manager.get_block(&2, &3), Block::SNOW);
+    assert_eq!(manager.get_block(&2, &2), manager.get_block(&20, &2));
+    assert_eq!(manager.get_block(&4, &3), Block::GRASS_BLOCK);
+}
@@ -0,0 +1,179 @@
+mod common;
+
+use common::temp_dir;
+use sand_engine::{
+    blocks::Block,
+    conversion,
+    gen::{
+        features::STRUCTURES_DIR, legacy::LegacyWorldGenerator, Generator, GeneratorKind,
+        GENERATOR_VERSION,
+    },
+    save::{WorldMeta, WorldSave},
+    WorldManager,
+};
+use std::{collections::HashMap, fs};
+
+#[test]
+fn worlds_keep_the_generator_version_they_were_created_with() {
+    // Written before the generator had a version
+    let old: GeneratorKind = serde_json::from_str(r#"{ "type": "default" }"#).unwrap();
+    assert!(matches!(old, GeneratorKind::Default { version: 1, .. }));
+    let (gen, legacy) = (old.build(5).unwrap(), LegacyWorldGenerator::new(5));
+    for (x, y) in [(0, 0), (-300, 40), (1000, 60), (77, -20)] {
+        assert_eq!(gen.gen_block(&x, &y), legacy.gen_block(&x, &y));
+    }
+
+    let new: GeneratorKind =
+        serde_json::from_str(&serde_json::to_string(&GeneratorKind::default()).unwrap()).unwrap();
+    assert!(matches!(
+        new,
+        GeneratorKind::Default {
+            version: GENERATOR_VERSION,
+            ..
+        }
+    ));
+
+    let newer = format!(
+        r#"{{ "type": "default", "version": {} }}"#,
+        GENERATOR_VERSION + 1
+    );
+    let newer: GeneratorKind = serde_json::from_str(&newer).unwrap();
+    assert!(newer.build(5).is_err());
+}
+
+#[test]
+fn structures_are_kept_with_the_world() {
+    let kind = GeneratorKind::with_structures(STRUCTURES_DIR).unwrap();
+    let GeneratorKind::Default { structures, .. } = &kind else {
+        panic!("{kind:?}");
+    };
+    assert!(!structures.is_empty());
+    let saved: GeneratorKind =
+        serde_json::from_str(&serde_json::to_string(&kind).unwrap()).unwrap();
+    assert!(
+        matches!(saved, GeneratorKind::Default { structures: ref saved, .. } if saved.len() == structures.len())
+    );
+    assert!(saved.build(5).is_ok());
+
+    assert!(GeneratorKind::with_structures("no such folder").is_err());
+}
+
+/// Origins of the chunks around the surface of a strip of 48 chunks crossing a region border
+fn surface_chunks(gen: &dyn Generator) -> Vec<(i64, i64)> {
+    let mut origins = Vec::new();
+    for origin_x in (-24 * 16..24 * 16).step_by(16) {
+        let surface = gen.surface_height(&(origin_x + 8)).unwrap();
+        let (_, bottom) = conversion::get_chunk_world_cords(&origin_x, &(surface - 16));
+        for origin_y in (bottom..bottom + 48).step_by(16) {
+            origins.push((origin_x, origin_y));
+        }
+    }
+    origins
+}
+
+fn gen_at(gen: &dyn Generator, origin: &(i64, i64)) -> [Block; 16 * 16] {
+    let (region_x, region_y) = conversion::get_region_cords(&origin.0, &origin.1);
+    let (chunk_x, chunk_y) = conversion::get_region_chunk_cords(&origin.0, &origin.1);
+    gen.gen_chunk(&region_x, &region_y, &chunk_x, &chunk_y)
+        .blocks()
+}
+
+#[test]
+fn chunks_do_not_depend_on_generation_order() {
+    let kind = GeneratorKind::with_structures(STRUCTURES_DIR).unwrap();
+    let (first, second) = (kind.build(11).unwrap(), kind.build(11).unwrap());
+    let origins = surface_chunks(first.as_ref());
+
+    let forwards: HashMap<_, _> = origins
+        .iter()
+        .map(|origin| (*origin, gen_at(first.as_ref(), origin)))
+        .collect();
+    // Every other chunk backwards, then the rest
+    let mut shuffled: Vec<_> = origins.iter().rev().step_by(2).collect();
+    shuffled.extend(origins.iter().rev().skip(1).step_by(2));
+    let backwards: HashMap<_, _> = shuffled
+        .into_iter()
+        .map(|origin| (*origin, gen_at(second.as_ref(), origin)))
+        .collect();
+    assert_eq!(forwards.len(), backwards.len());
+    for (origin, blocks) in &forwards {
+        assert!(&backwards[origin] == blocks, "chunk at {origin:?}");
+    }
+
+    // Some feature is cut in two by a chunk border, so both sides had to
+    // place their part of it
+    let features = [Block::OAK_WOOD, Block::OAK_LEAVE, Block::CACTUS];
+    let crosses = forwards.iter().any(|((origin_x, origin_y), left)| {
+        let Some(right) = forwards.get(&(origin_x + 16, *origin_y)) else {
+            return false;
+        };
+        (0..16).any(|y| features.contains(&left[15 + 16 * y]) && features.contains(&right[16 * y]))
+    });
+    assert!(crosses);
+}
+
+#[test]
+fn broken_terrain_settings_are_rejected() {
+    let build = |terrain: &str| {
+        let json = format!(r#"{{ "type": "default", "version": 2, "terrain": {terrain} }}"#);
+        serde_json::from_str::<GeneratorKind>(&json).map(|kind| kind.build(0).map(|_| ()))
+    };
+    assert!(build(r#"{ "octaves": 4, "lacunarity": 2.5 }"#)
+        .unwrap()
+        .is_ok());
+    for terrain in [
+        r#"{ "wavelength": 0 }"#,
+        r#"{ "wavelength": -256 }"#,
+        r#"{ "octaves": 0 }"#,
+        r#"{ "octaves": 1000 }"#,
+        r#"{ "lacunarity": 0 }"#,
+        r#"{ "persistence": -1 }"#,
+        r#"{ "beach_sand_depth": 12 }"#,
+    ] {
+        assert!(build(terrain).unwrap().is_err(), "{terrain}");
+    }
+    // Caught by serde already
+    assert!(build(r#"{ "octaves": -2 }"#).is_err());
+}
+
+#[test]
+fn worlds_whose_terrain_changed_are_refused() {
+    let dir = temp_dir("check");
+    let mut meta = WorldMeta::new("check", 3);
+    meta.generator = GeneratorKind::flat();
+    let mut manager = WorldManager::new(WorldSave::create(&dir, meta).unwrap()).unwrap();
+    manager.save_all().unwrap();
+    drop(manager);
+    assert!(WorldManager::new(WorldSave::open(&dir).unwrap()).is_ok());
+
+    // Someone edited the layers of the world after it was created
+    let mut world = WorldSave::open(&dir).unwrap();
+    assert!(world.meta.generator_check.is_some());
+    let GeneratorKind::Flat { layers, .. } = &mut world.meta.generator else {
+        panic!("{:?}", world.meta.generator);
+    };
+    layers[1].height += 2;
+    let error = WorldManager::new(world).err().unwrap();
+    assert!(
+        format!("{error:#}").contains("different terrain"),
+        "{error:#}"
+    );
+}
+
+#[test]
+fn worlds_without_a_generator_keep_the_original_terrain() {
+    let dir = temp_dir("no-generator");
+    // Written before worlds recorded their generator
+    fs::write(dir.join("world.json"), r#"{ "name": "Old", "seed": 1234 }"#).unwrap();
+
+    let world = WorldSave::open(&dir).unwrap();
+    assert!(matches!(
+        world.meta.generator,
+        GeneratorKind::Default { version: 1, .. }
+    ));
+    let gen = world.meta.generator.build(world.meta.seed).unwrap();
+    let legacy = LegacyWorldGenerator::new(1234);
+    for (x, y) in [(0, 0), (-300, 40), (1000, 60), (77, -20)] {
+        assert_eq!(gen.gen_block(&x, &y), legacy.gen_block(&x, &y));
+    }
+}
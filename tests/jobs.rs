@@ -0,0 +1,102 @@
+use sand_engine::{
+    blocks::Block,
+    chunks::Chunk,
+    gen::Generator,
+    jobs::{JobKind, JobPool, JobResult},
+};
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Generator that tells when it starts a chunk and waits to be released,
+/// dropping the sender releases every chunk
+struct GatedGenerator {
+    started: Mutex<Sender<()>>,
+    release: Mutex<Receiver<()>>,
+}
+impl Generator for GatedGenerator {
+    fn gen_block(&self, _world_x: &i64, _world_y: &i64) -> Block {
+        Block::AIR
+    }
+    fn gen_chunk(&self, _region_x: &i32, _region_y: &i32, _chunk_x: &u8, _chunk_y: &u8) -> Chunk {
+        let _ = self.started.lock().unwrap().send(());
+        let _ = self.release.lock().unwrap().recv();
+        Chunk::new([Block::AIR; 16 * 16])
+    }
+}
+
+/// Pool of one worker that is busy with chunk 0 until the returned sender releases it
+fn busy_pool() -> (JobPool, Sender<()>) {
+    let (started, wait) = mpsc::channel();
+    let (release, gate) = mpsc::channel();
+    let gen = GatedGenerator {
+        started: Mutex::new(started),
+        release: Mutex::new(gate),
+    };
+    let pool = JobPool::with_threads(Arc::new(gen), 1);
+    pool.submit(0, generate(0));
+    wait.recv().unwrap();
+    (pool, release)
+}
+
+fn generate(chunk_x: u8) -> JobKind {
+    JobKind::Generate {
+        region: (0, 0),
+        chunk: (chunk_x, 0),
+    }
+}
+
+/// Chunks of the next `count` results in the order they arrive
+fn collect(pool: &JobPool, count: usize) -> Vec<u8> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut chunks = Vec::new();
+    while chunks.len() < count {
+        assert!(Instant::now() < deadline, "Jobs never finished");
+        chunks.extend(pool.results().map(|result| match result {
+            JobResult::Generated { chunk, .. } => chunk.0,
+            JobResult::Meshed { .. } => panic!("Nothing was meshed"),
+        }));
+    }
+    chunks
+}
+
+#[test]
+fn lower_priorities_run_first() {
+    let (pool, release) = busy_pool();
+    for (chunk_x, priority) in [(1, 50), (2, 10), (3, 30), (4, 10), (5, 0)] {
+        pool.submit(priority, generate(chunk_x));
+    }
+    drop(release);
+    // Equal priorities keep the order they were submitted in
+    assert_eq!(collect(&pool, 6), vec![0, 5, 2, 4, 3, 1]);
+}
+
+#[test]
+fn cancelled_jobs_never_run() {
+    let (pool, release) = busy_pool();
+    pool.submit(1, generate(1));
+    pool.submit(2, generate(2));
+
+    let mut cancelled: Vec<_> = pool
+        .cancel_pending()
+        .into_iter()
+        .map(|job| match job {
+            JobKind::Generate { chunk, .. } => chunk.0,
+            JobKind::Mesh { .. } => panic!("Nothing was meshed"),
+        })
+        .collect();
+    cancelled.sort();
+    assert_eq!(cancelled, vec![1, 2]);
+    assert!(pool.cancel_pending().is_empty());
+
+    // The running job still finishes, later jobs run as usual
+    pool.submit(3, generate(3));
+    drop(release);
+    assert_eq!(collect(&pool, 2), vec![0, 3]);
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(pool.results().count(), 0);
+}
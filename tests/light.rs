@@ -0,0 +1,64 @@
+mod common;
+
+use common::flat_world;
+use sand_engine::{blocks::Block, WorldRect, MAX_LIGHT};
+
+#[test]
+fn sunlight_reaches_the_surface_but_not_underground() {
+    let mut manager = flat_world("sunlight");
+    assert_eq!(manager.get_light(&0, &20), MAX_LIGHT);
+    assert_eq!(manager.get_light(&0, &4), MAX_LIGHT);
+    // The grass block is lit on its face
+    assert_eq!(manager.get_light(&0, &3), MAX_LIGHT);
+    assert_eq!(manager.get_light(&0, &-20), 0);
+}
+
+#[test]
+fn glowing_blocks_light_up_caves_until_removed() {
+    let mut manager = flat_world("glow");
+    manager.fill_rect(&WorldRect::new(-10, -40, 10, -30), Block::AIR);
+    assert_eq!(manager.get_light(&3, &-35), 0);
+
+    manager.set_block(&0, &-35, Block::FIRE);
+    let glow = Block::FIRE.light();
+    assert_eq!(manager.get_light(&0, &-35), glow);
+    assert_eq!(manager.get_light(&3, &-35), glow - 3);
+    assert_eq!(manager.get_light(&2, &-34), glow - 3);
+    // Cave walls are lit on their face
+    assert_eq!(manager.get_light(&0, &-41), glow - 5);
+
+    manager.set_block(&0, &-35, Block::AIR);
+    assert_eq!(manager.get_light(&3, &-35), 0);
+}
+
+#[test]
+fn roofs_cast_shadows_across_chunk_borders() {
+    let mut manager = flat_world("roof");
+    let roof = WorldRect::new(-12, 10, 12, 10);
+    manager.fill_rect(&roof, Block::STONE);
+    // Sunlight only creeps in from the sides, losing a level per block
+    assert_eq!(manager.get_light(&0, &5), 0);
+    assert_eq!(manager.get_light(&-12, &5), MAX_LIGHT - 1);
+    assert_eq!(manager.get_light(&12, &5), MAX_LIGHT - 1);
+    assert_eq!(manager.get_light(&10, &5), MAX_LIGHT - 3);
+
+    manager.fill_rect(&roof, Block::AIR);
+    assert_eq!(manager.get_light(&0, &5), MAX_LIGHT);
+}
+
+#[test]
+fn roofs_far_above_shade_every_chunk_below() {
+    let mut manager = flat_world("high-roof");
+    assert_eq!(manager.get_light(&0, &4), MAX_LIGHT);
+
+    // Further above the chunk border at y 16 than the light of a chunk reaches
+    let roof = WorldRect::new(-60, 30, 60, 30);
+    manager.fill_rect(&roof, Block::STONE);
+    assert_eq!(manager.get_light(&0, &16), 0);
+    assert_eq!(manager.get_light(&0, &15), 0);
+    assert_eq!(manager.get_light(&0, &4), 0);
+
+    manager.fill_rect(&roof, Block::AIR);
+    assert_eq!(manager.get_light(&0, &15), MAX_LIGHT);
+    assert_eq!(manager.get_light(&0, &4), MAX_LIGHT);
+}
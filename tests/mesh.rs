@@ -0,0 +1,370 @@
+use macroquad::prelude::{Rect, WHITE};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sand_engine::{
+    blocks::Block,
+    chunks::Chunk,
+    gen::{terrain::TerrainConfig, Generator, WorldGenerator},
+    Atlas, ChunkMesh, ChunkWindow, Decorations, MeshType, Quad, TEXTURE_SHEET,
+};
+
+/// Chunk of random blocks picked from `palette`
+fn random_chunk(rng: &mut StdRng, palette: &[Block]) -> Chunk {
+    Chunk::new(std::array::from_fn(|_| {
+        palette[rng.gen_range(0..palette.len())]
+    }))
+}
+
+/// Chunks worth comparing meshers on, random noise and generated terrain
+fn sample_chunks() -> Vec<Chunk> {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut chunks: Vec<_> = (0..50)
+        .map(|i| match i % 3 {
+            0 => random_chunk(&mut rng, &[Block::AIR, Block::STONE]),
+            1 => random_chunk(&mut rng, &[Block::AIR, Block::SAND, Block::WATER_EDGE]),
+            _ => random_chunk(
+                &mut rng,
+                &[Block::DIRT, Block::DIRT, Block::DIRT, Block::STONE],
+            ),
+        })
+        .collect();
+
+    let gen = WorldGenerator::new(7, TerrainConfig::default(), vec![]);
+    for chunk_y in 0..16 {
+        for chunk_x in 0..4 {
+            chunks.push(gen.gen_chunk(&0, &0, &chunk_x, &chunk_y));
+        }
+    }
+    chunks
+}
+
+#[test]
+fn geometry_has_a_quad_per_rect() {
+    let mut blocks = [Block::AIR; 16 * 16];
+    blocks[0] = Block::STONE;
+    blocks[1 + 16] = Block::SAND;
+    let chunk_mesh = ChunkMesh::greedy_mesh(&Chunk::new(blocks));
+    let mesh = chunk_mesh.to_mesh();
+
+    assert_eq!(mesh.vertices.len(), chunk_mesh.mesh.len() * 4);
+    assert_eq!(mesh.indices.len(), chunk_mesh.mesh.len() * 6);
+    assert!(mesh
+        .indices
+        .iter()
+        .all(|i| (*i as usize) < mesh.vertices.len()));
+}
+
+#[test]
+fn geometry_is_flipped_to_screen_space() {
+    let mut blocks = [Block::AIR; 16 * 16];
+    // Bottom right block of the chunk in world space
+    blocks[0] = Block::STONE;
+    let mesh = ChunkMesh::greedy_mesh(&Chunk::new(blocks)).to_mesh();
+
+    for vertex in &mesh.vertices {
+        assert!((15.0..=16.0).contains(&vertex.position.x));
+        assert!((15.0..=16.0).contains(&vertex.position.y));
+        assert_eq!(vertex.color, Block::STONE.color());
+    }
+}
+
+#[test]
+fn rebuilt_meshes_get_new_ids() {
+    let chunk = Chunk::new([Block::DIRT; 16 * 16]);
+    let first = ChunkMesh::greedy_mesh(&chunk);
+    let second = ChunkMesh::greedy_mesh(&chunk);
+    assert_ne!(first.id, second.id);
+}
+
+/// Pixel of a rasterized chunk for a block in world space
+fn pixel(pixels: &[u8; 16 * 16 * 4], x: usize, y: usize) -> [u8; 4] {
+    let i = ((15 - x) + (15 - y) * 16) * 4;
+    pixels[i..i + 4].try_into().unwrap()
+}
+
+#[test]
+fn rasterized_greedy_and_culled_meshes_match() {
+    let mut blocks = [Block::AIR; 16 * 16];
+    for (i, block) in blocks.iter_mut().enumerate() {
+        *block = match i % 7 {
+            0 => Block::STONE,
+            1 | 2 => Block::SAND,
+            3 => Block::DIRT,
+            _ => Block::AIR,
+        };
+    }
+    let chunk = Chunk::new(blocks);
+    let greedy = ChunkMesh::greedy_mesh(&chunk).rasterize();
+    let culled = ChunkMesh::culled_mesh(&chunk).rasterize();
+    assert!(greedy
+        .chunks(4)
+        .zip(culled.chunks(4))
+        .all(|(g, c)| g == c || g[3] == 0 && c[3] == 0));
+
+    for x in 0..16 {
+        for y in 0..16 {
+            let expected: [u8; 4] = blocks[x + y * 16].color().into();
+            assert_eq!(pixel(&culled, x, y), expected, "block {x} {y}");
+        }
+    }
+}
+
+#[test]
+fn rasterized_water_edge_is_water_colored() {
+    let mut blocks = [Block::AIR; 16 * 16];
+    blocks[3] = Block::WATER_EDGE;
+    let pixels = ChunkMesh::greedy_mesh(&Chunk::new(blocks)).rasterize();
+    let expected: [u8; 4] = Block::WATER_EDGE.color().into();
+    assert_eq!(pixel(&pixels, 3, 0), expected);
+    assert_eq!(pixel(&pixels, 4, 0)[3], 0);
+    assert_eq!(pixel(&pixels, 3, 1)[3], 0);
+}
+
+#[test]
+fn atlas_tiles_are_numbered_by_rows() {
+    let atlas = Atlas::new(64, 32);
+    let tile = atlas.uv(5).unwrap();
+    assert_eq!((tile.x, tile.y, tile.w, tile.h), (0.25, 0.5, 0.25, 0.5));
+    assert!(atlas.uv(8).is_none());
+}
+
+#[test]
+fn texture_sheet_loads() {
+    let (atlas, image) = Atlas::load(TEXTURE_SHEET).unwrap();
+    assert!(image.width > 0 && image.height > 0);
+    assert!(atlas.uv(0).is_some());
+}
+
+#[test]
+fn textured_quads_tile_per_block() {
+    let atlas = Atlas::new(64, 64);
+    let chunk = Chunk::new([Block::GRASS_BLOCK; 16 * 16]);
+    let chunk_mesh = ChunkMesh::greedy_mesh(&chunk);
+    let (flat, textured) = chunk_mesh.to_meshes(Some(&atlas));
+
+    assert!(flat.vertices.is_empty());
+    assert_eq!(textured.vertices.len(), 16 * 16 * 4);
+    let tile = atlas
+        .uv(Block::GRASS_BLOCK.definition().texture.unwrap())
+        .unwrap();
+    for vertex in &textured.vertices {
+        assert!(vertex.uv.x >= tile.x && vertex.uv.x <= tile.x + tile.w);
+        assert!(vertex.uv.y >= tile.y && vertex.uv.y <= tile.y + tile.h);
+    }
+}
+
+#[test]
+fn textured_quads_fall_back_to_colors() {
+    let chunk = Chunk::new([Block::GRASS_BLOCK; 16 * 16]);
+    let chunk_mesh = ChunkMesh::greedy_mesh(&chunk);
+    let (flat, textured) = chunk_mesh.to_meshes(None);
+    assert!(textured.vertices.is_empty());
+    assert_eq!(flat.vertices.len(), chunk_mesh.mesh.len() * 4);
+    assert_eq!(flat.vertices[0].color, Block::GRASS_BLOCK.color());
+}
+
+#[test]
+fn culled_mesh_skips_air() {
+    let mut blocks = [Block::AIR; 16 * 16];
+    blocks[17] = Block::STONE;
+    blocks[200] = Block::DIRT;
+    let chunk_mesh = ChunkMesh::culled_mesh(&Chunk::new(blocks));
+    assert_eq!(chunk_mesh.mesh.len(), 2);
+    assert!(ChunkMesh::culled_mesh(&Chunk::new([Block::AIR; 16 * 16]))
+        .mesh
+        .is_empty());
+}
+
+#[test]
+fn build_uses_the_chosen_mesher() {
+    let chunk = Chunk::new([Block::STONE; 16 * 16]);
+    let culled = ChunkMesh::build(&chunk, MeshType::CULLED);
+    let greedy = ChunkMesh::build(&chunk, MeshType::GREEDY);
+    assert_eq!(culled.mesh_type, MeshType::CULLED);
+    assert_eq!(culled.mesh.len(), 16 * 16);
+    assert_eq!(greedy.mesh_type, MeshType::GREEDY);
+    assert_eq!(greedy.mesh.len(), 1);
+}
+
+/// Compares both meshers on the same chunks
+#[test]
+fn meshers_agree() {
+    let (mut culled_quads, mut greedy_quads) = (0, 0);
+    for (i, chunk) in sample_chunks().iter().enumerate() {
+        let culled = ChunkMesh::culled_mesh(chunk);
+        let greedy = ChunkMesh::greedy_mesh(chunk);
+        assert_eq!(culled.rasterize(), greedy.rasterize(), "chunk {i}");
+        assert!(greedy.mesh.len() <= culled.mesh.len(), "chunk {i}");
+        culled_quads += culled.mesh.len();
+        greedy_quads += greedy.mesh.len();
+    }
+    // Merging has to pay off across the samples, not only break even
+    assert!(
+        greedy_quads < culled_quads,
+        "culled: {culled_quads} quads, greedy: {greedy_quads} quads"
+    );
+}
+
+/// Checks that undecorated quads cover every visible block exactly once in its color and nothing else
+fn assert_exact_cover(chunk: &Chunk, chunk_mesh: &ChunkMesh) {
+    let blocks = chunk.blocks();
+    let mut covered = [0; 16 * 16];
+    for quad in &chunk_mesh.mesh {
+        let Rect { x, y, w, h } = quad.rect;
+        assert!(x.fract() == 0. && y.fract() == 0. && w.fract() == 0. && h.fract() == 0.);
+        assert!(w >= 1. && h >= 1. && x + w <= 16. && y + h <= 16.);
+        for cell_y in y as usize..(y + h) as usize {
+            for cell_x in x as usize..(x + w) as usize {
+                let block = blocks[cell_x + cell_y * 16];
+                assert_eq!(quad.color, block.color(), "block {cell_x} {cell_y}");
+                covered[cell_x + cell_y * 16] += 1;
+            }
+        }
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        let expected = if block.color().a > 0. { 1 } else { 0 };
+        assert_eq!(covered[i], expected, "{block:?} at {} {}", i % 16, i / 16);
+    }
+}
+
+#[test]
+fn meshes_cover_every_visible_block_once() {
+    let palettes: [&[Block]; 4] = [
+        &[Block::AIR, Block::STONE],
+        &[Block::STONE, Block::DIRT],
+        &[Block::AIR, Block::SAND, Block::WATER, Block::WATER_EDGE],
+        &[Block::AIR, Block::AIR, Block::AIR, Block::OAK_LEAVE],
+    ];
+    let mut rng = StdRng::seed_from_u64(18);
+    let undecorated = Decorations::default();
+    for case in 0..400 {
+        let chunk = random_chunk(&mut rng, palettes[case % palettes.len()]);
+        let greedy = ChunkMesh::greedy_mesh_with(&chunk, &undecorated);
+        let culled = ChunkMesh::culled_mesh_with(&chunk, &undecorated);
+        assert_exact_cover(&chunk, &greedy);
+        assert_exact_cover(&chunk, &culled);
+        assert!(greedy.mesh.len() <= culled.mesh.len());
+    }
+    for chunk in sample_chunks() {
+        assert_exact_cover(&chunk, &ChunkMesh::greedy_mesh_with(&chunk, &undecorated));
+    }
+}
+
+#[test]
+fn greedy_merges_whole_rows_and_columns() {
+    let rows = Chunk::new(std::array::from_fn(|i| {
+        if (i / 16) % 2 == 0 {
+            Block::STONE
+        } else {
+            Block::DIRT
+        }
+    }));
+    assert_eq!(ChunkMesh::greedy_mesh(&rows).mesh.len(), 16);
+
+    let columns = Chunk::new(std::array::from_fn(|i| {
+        if (i % 16) % 2 == 0 {
+            Block::STONE
+        } else {
+            Block::DIRT
+        }
+    }));
+    assert_eq!(ChunkMesh::greedy_mesh(&columns).mesh.len(), 16);
+
+    // A solid chunk with a single notch only needs a couple of rects
+    let mut blocks = [Block::STONE; 16 * 16];
+    blocks[15 + 15 * 16] = Block::AIR;
+    assert_eq!(ChunkMesh::greedy_mesh(&Chunk::new(blocks)).mesh.len(), 2);
+}
+
+#[test]
+fn decorations_replace_block_quads() {
+    let mut blocks = [Block::AIR; 16 * 16];
+    blocks[0] = Block::WATER_EDGE;
+    blocks[1] = Block::STONE;
+    let chunk = Chunk::new(blocks);
+
+    let foam = ChunkMesh::greedy_mesh(&chunk);
+    assert_eq!(foam.mesh.len(), 3);
+    assert!(foam.mesh.iter().any(|quad| quad.color == WHITE));
+
+    let mut decorations = Decorations::default();
+    decorations.register(Block::STONE, |block, rect| {
+        vec![Quad::new(block, rect), Quad::new(block, rect)]
+    });
+    let decorated = ChunkMesh::greedy_mesh_with(&chunk, &decorations);
+    assert_eq!(decorated.mesh.len(), 3);
+    assert!(decorated.mesh.iter().all(|quad| quad.color != WHITE));
+}
+
+#[test]
+fn depth_counts_steps_to_the_nearest_air() {
+    // Air only left of the chunk, within the neighbouring chunk
+    let window = ChunkWindow::new(|x, _| if x < 0 { Block::AIR } else { Block::STONE });
+    let depths = window.depths();
+    for y in 0..16 {
+        for x in 0..16 {
+            let expected = (x as u8).min(ChunkMesh::SHADE_DEPTH);
+            assert_eq!(depths[x + y * 16], expected, "block {x} {y}");
+        }
+    }
+
+    // On its own the same chunk is surrounded by air on every side
+    let isolated = ChunkWindow::isolated(&Chunk::new(window.chunk_blocks())).depths();
+    assert_eq!(isolated[15 + 8 * 16], 0);
+    assert_eq!(isolated[7 + 8 * 16], ChunkMesh::SHADE_DEPTH);
+}
+
+#[test]
+fn shading_matches_across_chunk_borders() {
+    let mut rng = StdRng::seed_from_u64(19);
+    let world: Vec<Block> = (0..48 * 48)
+        .map(|_| {
+            if rng.gen_range(0..10) == 0 {
+                Block::AIR
+            } else {
+                Block::STONE
+            }
+        })
+        .collect();
+    let block_at = |x: i64, y: i64| {
+        if (0..48).contains(&x) && (0..48).contains(&y) {
+            world[(x + y * 48) as usize]
+        } else {
+            Block::STONE
+        }
+    };
+
+    // Depth of a block must not depend on which chunk it is meshed from, so a
+    // window straddling two chunks has to agree with both of them
+    let middle = ChunkWindow::new(|x, y| block_at(x + 16, y + 16)).depths();
+    let left = ChunkWindow::new(|x, y| block_at(x, y + 16)).depths();
+    let straddling = ChunkWindow::new(|x, y| block_at(x + 8, y + 16)).depths();
+    for y in 0..16 {
+        for x in 0..8 {
+            assert_eq!(straddling[x + y * 16], left[x + 8 + y * 16]);
+        }
+        for x in 8..16 {
+            assert_eq!(straddling[x + y * 16], middle[x - 8 + y * 16]);
+        }
+    }
+}
+
+#[test]
+fn shaded_meshes_still_cover_every_block() {
+    let mut rng = StdRng::seed_from_u64(20);
+    let undecorated = Decorations::default();
+    for _ in 0..100 {
+        let chunk = random_chunk(&mut rng, &[Block::AIR, Block::STONE, Block::STONE]);
+        let window = ChunkWindow::isolated(&chunk);
+        for mesh_type in [MeshType::GREEDY, MeshType::CULLED] {
+            let chunk_mesh = ChunkMesh::build_shaded(&window, None, mesh_type, &undecorated);
+            assert_exact_cover(&chunk, &chunk_mesh);
+            let depths = window.depths();
+            for quad in &chunk_mesh.mesh {
+                let i = quad.rect.x as usize + quad.rect.y as usize * 16;
+                assert!(quad.brightness <= 1.);
+                assert!(quad.brightness > 0.);
+                assert_eq!(depths[i] == 0, quad.brightness == 1.);
+            }
+        }
+    }
+}
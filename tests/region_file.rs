@@ -0,0 +1,188 @@
+mod common;
+
+use common::temp_dir;
+use sand_engine::{
+    blocks::Block,
+    chunks::Chunk,
+    gen::GeneratorKind,
+    region_file::{self, MAGIC},
+    save::{WorldMeta, WorldSave},
+    Region, WorldManager,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Region with a uniform, a striped and a noisy modified chunk
+fn sample_region() -> Region {
+    let mut region = Region::new_empty(&-3, &7);
+    let chunks = [
+        (0, Chunk::new([Block::STONE; 16 * 16])),
+        (
+            17,
+            Chunk::new(std::array::from_fn(|i| Block((i / 16 % 3) as u16))),
+        ),
+        (
+            255,
+            Chunk::new(std::array::from_fn(|i| Block((i * 7 % 18) as u16))),
+        ),
+    ];
+    for (index, mut chunk) in chunks {
+        chunk.modified = true;
+        region.chunks[index] = Some(chunk);
+    }
+    region
+}
+
+#[test]
+fn regions_survive_a_round_trip() {
+    let region = sample_region();
+    let bytes = region_file::encode(&region);
+    assert!(bytes.starts_with(&MAGIC));
+    // The uniform chunk takes a single run instead of 256 raw ids
+    assert!(bytes.len() < 2066 + 3 * 5 + 2 * 512 + 3);
+
+    let decoded = region_file::decode(&bytes, &-3, &7).unwrap();
+    assert!(!decoded.dirty);
+    for (index, chunk) in region.chunks.iter().enumerate() {
+        let decoded = decoded.chunks[index].as_ref();
+        assert_eq!(
+            chunk.as_ref().map(Chunk::blocks),
+            decoded.map(Chunk::blocks)
+        );
+        assert!(decoded.is_none_or(|chunk| chunk.modified));
+    }
+}
+
+#[test]
+fn damaged_files_are_rejected() {
+    let bytes = region_file::encode(&sample_region());
+    assert!(region_file::decode(&bytes, &0, &0).is_err());
+
+    for at in [5, 100, bytes.len() - 1] {
+        let mut damaged = bytes.clone();
+        damaged[at] ^= 0x10;
+        assert!(region_file::decode(&damaged, &-3, &7).is_err(), "byte {at}");
+    }
+    assert!(region_file::decode(&bytes[..bytes.len() - 10], &-3, &7).is_err());
+    assert!(region_file::decode(&bytes[..100], &-3, &7).is_err());
+}
+
+#[test]
+fn legacy_regions_are_migrated() {
+    // Bincode layout regions were saved with before the header
+    let region = sample_region();
+    let mut bytes = bincode::serialize(&(-3i32, 7i32)).unwrap();
+    for chunk in &region.chunks {
+        bytes.extend(bincode::serialize(chunk).unwrap());
+    }
+
+    let migrated = region_file::decode(&bytes, &-3, &7).unwrap();
+    assert!(migrated.dirty);
+    assert_eq!(
+        migrated.chunks[17].as_ref().map(Chunk::blocks),
+        region.chunks[17].as_ref().map(Chunk::blocks)
+    );
+
+    let dir = temp_dir("migrate");
+    migrated.save(&dir).unwrap();
+    let path = Region::get_region_path(&dir, &-3, &7);
+    assert!(fs::read(path).unwrap().starts_with(&MAGIC));
+}
+
+/// Files unreadable region files were moved to
+fn set_aside_files(regions_dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(regions_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(".unreadable"))
+        .collect()
+}
+
+#[test]
+fn unreadable_files_are_reported_and_kept() {
+    let dir = temp_dir("unreadable");
+    let mut meta = WorldMeta::new("unreadable", 0);
+    meta.generator = GeneratorKind::flat();
+    let world = WorldSave::create(&dir, meta).unwrap();
+    let regions_dir = world.regions_dir();
+    let path = Region::get_region_path(&regions_dir, &0, &0);
+    fs::write(&path, b"not a region").unwrap();
+
+    let mut manager = WorldManager::new(world).unwrap();
+    // The region starts over from the generator
+    assert_eq!(manager.get_block(&0, &3), Block::GRASS_BLOCK);
+    let error = manager.clean().unwrap_err();
+    assert!(format!("{error:#}").contains("unreadable"), "{error:#}");
+    assert!(!path.exists());
+    drop(manager);
+
+    // A second broken file in the same second does not replace the first
+    fs::write(&path, b"not a region either").unwrap();
+    let world = WorldSave::open(&dir).unwrap();
+    let mut manager = WorldManager::new(world).unwrap();
+    manager.get_block(&0, &3);
+    assert!(manager.clean().is_err());
+    let mut contents: Vec<_> = set_aside_files(&regions_dir)
+        .iter()
+        .map(|file| fs::read(file).unwrap())
+        .collect();
+    contents.sort();
+    assert_eq!(
+        contents,
+        vec![b"not a region".to_vec(), b"not a region either".to_vec()]
+    );
+}
+
+#[test]
+fn read_only_regions_are_never_saved() {
+    let dir = temp_dir("read-only");
+    let path = Region::get_region_path(&dir, &0, &0);
+    fs::write(&path, b"not a region").unwrap();
+
+    let mut region = Region::new_empty(&0, &0);
+    region.read_only = true;
+    region.dirty = true;
+    assert!(region.save(&dir).is_err());
+    assert_eq!(fs::read(&path).unwrap(), b"not a region");
+}
+
+#[test]
+fn regions_are_saved_when_the_metadata_can_not_be() {
+    let dir = temp_dir("meta-fails");
+    let world = WorldSave::create(&dir, WorldMeta::new("meta-fails", 0)).unwrap();
+    let regions_dir = world.regions_dir();
+    let mut manager = WorldManager::new(world).unwrap();
+    manager.set_block(&0, &0, Block::FIRE);
+
+    // A folder in the way of world.json makes writing it fail
+    fs::remove_file(dir.join("world.json")).unwrap();
+    fs::create_dir(dir.join("world.json")).unwrap();
+    assert!(manager.save_all().is_err());
+    let saved = Region::load(&regions_dir, &0, &0).unwrap();
+    assert_eq!(saved.peek_block(&0, &0, &0, &0), Some(Block::FIRE));
+}
+
+#[test]
+fn every_failed_region_file_is_reported() {
+    let dir = temp_dir("many-unreadable");
+    let mut meta = WorldMeta::new("many-unreadable", 0);
+    meta.generator = GeneratorKind::flat();
+    let world = WorldSave::create(&dir, meta).unwrap();
+    for region_x in [0, 1, 2] {
+        let path = Region::get_region_path(&world.regions_dir(), &region_x, &0);
+        fs::write(path, b"not a region").unwrap();
+    }
+
+    let mut manager = WorldManager::new(world).unwrap();
+    for world_x in [0, 256, 512] {
+        manager.get_block(&world_x, &3);
+    }
+    let error = format!("{:#}", manager.clean().unwrap_err());
+    assert!(error.starts_with("3 region files"), "{error}");
+    for file in ["0.0.rf", "1.0.rf", "2.0.rf"] {
+        assert!(error.contains(file), "{error}");
+    }
+    assert_eq!(error.matches("moved it to").count(), 3, "{error}");
+}
@@ -0,0 +1,14 @@
+use sand_engine::{blocks::Block, registry::BlockRegistry};
+
+#[test]
+fn flammability_must_be_a_probability() {
+    let builtin: Vec<_> = BlockRegistry::builtin().blocks().cloned().collect();
+    assert!(BlockRegistry::new(builtin.clone()).is_ok());
+
+    for flammability in [-0.1, 1.5, f32::NAN, f32::INFINITY] {
+        let mut definitions = builtin.clone();
+        definitions[Block::GRASS_BLOCK.0 as usize].flammability = flammability;
+        let error = BlockRegistry::new(definitions).err().unwrap();
+        assert!(error.to_string().contains("flammability"), "{error}");
+    }
+}
@@ -0,0 +1,196 @@
+mod common;
+
+use common::flat_world;
+use sand_engine::{blocks::Block, WorldManager, WorldRect};
+
+/// Generates the chunks between -16 and 31 on both axes, blocks only move
+/// into loaded chunks
+fn load_around_origin(manager: &mut WorldManager) {
+    for chunk_x in [-16, 0, 16] {
+        for chunk_y in [-16, 0, 16] {
+            manager.get_block(&chunk_x, &chunk_y);
+        }
+    }
+}
+
+#[test]
+fn only_water_under_open_air_shows_a_surface() {
+    let mut manager = flat_world("surface");
+    // A pocket of water in the stone and a puddle on the grass
+    manager.set_block(&0, &-30, Block::WATER_EDGE);
+    manager.set_block(&0, &4, Block::WATER);
+    for _ in 0..4 {
+        manager.tick();
+    }
+    assert_eq!(manager.get_block(&0, &-30), Block::WATER);
+    assert_eq!(manager.get_block(&0, &4), Block::WATER_EDGE);
+}
+
+#[test]
+fn sand_falls_one_block_per_tick_until_it_lands() {
+    let mut manager = flat_world("sand-falls");
+    load_around_origin(&mut manager);
+    // Starts above a chunk border so it has to cross into the chunk below
+    manager.set_block(&0, &20, Block::SAND);
+    manager.tick();
+    assert_eq!(manager.get_block(&0, &20), Block::AIR);
+    assert_eq!(manager.get_block(&0, &19), Block::SAND);
+
+    for _ in 0..30 {
+        manager.tick();
+    }
+    // Lands on the grass and stays there
+    assert_eq!(manager.get_block(&0, &4), Block::SAND);
+    assert_eq!(manager.get_block(&0, &3), Block::GRASS_BLOCK);
+    assert!((5..=20).all(|y| manager.get_block(&0, &y) == Block::AIR));
+}
+
+#[test]
+fn sand_slides_off_slopes() {
+    let mut manager = flat_world("sand-slides");
+    load_around_origin(&mut manager);
+    // A grain on top of another one with a wall on its right
+    manager.set_block(&0, &4, Block::SAND);
+    manager.set_block(&0, &5, Block::SAND);
+    manager.set_block(&1, &4, Block::STONE);
+    for _ in 0..4 {
+        manager.tick();
+    }
+    assert_eq!(manager.get_block(&0, &4), Block::SAND);
+    assert_eq!(manager.get_block(&-1, &4), Block::SAND);
+    assert_eq!(manager.get_block(&0, &5), Block::AIR);
+    assert_eq!(manager.get_block(&1, &4), Block::STONE);
+}
+
+#[test]
+fn sand_piles_up_in_a_pit() {
+    let mut manager = flat_world("sand-pile");
+    load_around_origin(&mut manager);
+    // Walls on both sides leave no slope to slide off
+    for y in 4..8 {
+        manager.set_block(&-1, &y, Block::STONE);
+        manager.set_block(&1, &y, Block::STONE);
+    }
+    for y in 10..13 {
+        manager.set_block(&0, &y, Block::SAND);
+    }
+    for _ in 0..20 {
+        manager.tick();
+    }
+    assert!((4..7).all(|y| manager.get_block(&0, &y) == Block::SAND));
+    assert!((7..13).all(|y| manager.get_block(&0, &y) == Block::AIR));
+}
+
+#[test]
+fn fire_spreads_through_flammable_blocks_and_burns_them_away() {
+    let mut manager = flat_world("fire-spreads");
+    load_around_origin(&mut manager);
+    // A bush of leaves lit in the middle, floating so nothing else catches fire
+    let bush = WorldRect::new(-3, 5, 3, 11);
+    manager.fill_rect(&bush, Block::OAK_LEAVE);
+    manager.set_block(&0, &8, Block::FIRE);
+    let count = |manager: &WorldManager, block| {
+        bush.iter()
+            .filter(|(x, y)| manager.peek_block(x, y) == Some(block))
+            .count()
+    };
+
+    for _ in 0..50 {
+        manager.tick();
+    }
+    assert!(count(&manager, Block::FIRE) > 10);
+
+    for _ in 0..2000 {
+        manager.tick();
+    }
+    assert_eq!(count(&manager, Block::FIRE), 0);
+    assert!(count(&manager, Block::OAK_LEAVE) < 5);
+    assert_eq!(manager.get_active_chunk_count(), 0);
+}
+
+#[test]
+fn fire_burns_out() {
+    let mut manager = flat_world("fire-burns-out");
+    load_around_origin(&mut manager);
+    // Floating in the air with nothing to burn around it
+    manager.set_block(&0, &10, Block::FIRE);
+    for _ in 0..2000 {
+        manager.tick();
+    }
+    assert_eq!(manager.get_block(&0, &10), Block::AIR);
+    assert_eq!(manager.get_active_chunk_count(), 0);
+}
+
+#[test]
+fn water_puts_out_fire() {
+    let mut manager = flat_world("fire-water");
+    load_around_origin(&mut manager);
+    manager.set_block(&0, &4, Block::FIRE);
+    manager.set_block(&1, &4, Block::WATER);
+    manager.tick();
+    assert_eq!(manager.get_block(&0, &4), Block::AIR);
+}
+
+/// Draws a row of blocks, only water, stone and air are expected
+fn liquid_row(manager: &WorldManager, min_x: i64, max_x: i64, y: i64) -> String {
+    (min_x..=max_x)
+        .map(|x| match manager.peek_block(&x, &y).unwrap() {
+            Block::WATER => 'W',
+            Block::WATER_EDGE => '~',
+            Block::STONE => '#',
+            Block::AIR => '.',
+            block => panic!("Unexpected {block:?} at {x} {y}"),
+        })
+        .collect()
+}
+
+#[test]
+fn water_falls_until_it_lands() {
+    let mut manager = flat_world("water-falls");
+    load_around_origin(&mut manager);
+    manager.set_block(&0, &20, Block::WATER);
+    manager.tick();
+    assert_eq!(manager.get_block(&0, &19), Block::WATER_EDGE);
+
+    for _ in 0..30 {
+        manager.tick();
+    }
+    assert_eq!(manager.get_block(&0, &4), Block::WATER_EDGE);
+    assert!((5..=20).all(|y| manager.get_block(&0, &y) == Block::AIR));
+}
+
+#[test]
+fn water_spreads_sideways_and_levels_out() {
+    let mut manager = flat_world("water-levels");
+    load_around_origin(&mut manager);
+    // A column as high as the basin is wide
+    manager.fill_rect(&WorldRect::new(-1, 4, -1, 10), Block::STONE);
+    manager.fill_rect(&WorldRect::new(4, 4, 4, 10), Block::STONE);
+    manager.fill_rect(&WorldRect::new(0, 4, 0, 7), Block::WATER);
+    for _ in 0..50 {
+        manager.tick();
+    }
+    assert_eq!(liquid_row(&manager, -1, 4, 4), "#~~~~#");
+    assert_eq!(liquid_row(&manager, -1, 4, 5), "#....#");
+    assert_eq!(manager.get_active_chunk_count(), 0);
+}
+
+#[test]
+fn water_spills_over_a_wall_and_levels_out() {
+    let mut manager = flat_world("water-spills");
+    load_around_origin(&mut manager);
+    // A full basin next to an empty one, the wall between is lower than the water
+    manager.fill_rect(&WorldRect::new(-1, 4, -1, 12), Block::STONE);
+    manager.fill_rect(&WorldRect::new(8, 4, 8, 12), Block::STONE);
+    manager.fill_rect(&WorldRect::new(3, 4, 3, 6), Block::STONE);
+    manager.fill_rect(&WorldRect::new(0, 4, 2, 10), Block::WATER);
+    for _ in 0..100 {
+        manager.tick();
+    }
+    // Just enough water to fill both up to the top of the wall
+    assert_eq!(liquid_row(&manager, -1, 8, 4), "#WWW#WWWW#");
+    assert_eq!(liquid_row(&manager, -1, 8, 5), "#WWW#WWWW#");
+    assert_eq!(liquid_row(&manager, -1, 8, 6), "#~~~#~~~~#");
+    assert_eq!(liquid_row(&manager, -1, 8, 7), "#........#");
+    assert_eq!(manager.get_active_chunk_count(), 0);
+}
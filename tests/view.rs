@@ -0,0 +1,124 @@
+mod common;
+
+use common::flat_world;
+use macroquad::prelude::vec2;
+use sand_engine::{blocks::Block, ChunkMesh, WorldManager, WorldRect};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Keeps updating the view until every chunk in it has an up to date mesh
+fn wait_for_view(manager: &mut WorldManager, area: &WorldRect) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        manager.update_view(area);
+        let ready = manager.get_pending_job_count() == 0
+            && (area.min_y..=area.max_y).step_by(16).all(|y| {
+                (area.min_x..=area.max_x)
+                    .step_by(16)
+                    .all(|x| manager.chunk_mesh(&x, &y).is_some())
+            });
+        if ready {
+            return;
+        }
+        assert!(Instant::now() < deadline, "View never finished meshing");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn meshes_are_only_prepared_by_updating_the_view() {
+    let mut manager = flat_world("view");
+    assert!(manager.chunk_mesh(&0, &0).is_none());
+    assert_eq!(manager.get_region_count(), 0);
+
+    let area = WorldRect::new(-16, -16, 31, 31);
+    wait_for_view(&mut manager, &area);
+    assert_eq!(manager.get_pending_job_count(), 0);
+    assert!(manager
+        .chunk_mesh(&0, &0)
+        .is_some_and(|mesh| !mesh.mesh.is_empty()));
+    // Chunks outside the view are left alone
+    assert!(manager.chunk_mesh(&256, &0).is_none());
+}
+
+#[test]
+fn block_changes_are_remeshed_on_the_next_update() {
+    let mut manager = flat_world("remesh");
+    let area = WorldRect::new(0, 0, 15, 15);
+    wait_for_view(&mut manager, &area);
+    let id = manager.chunk_mesh(&0, &0).unwrap().id;
+
+    manager.set_block(&4, &10, Block::STONE);
+    // The old mesh stays on screen while the new one is built
+    assert_eq!(manager.chunk_mesh(&0, &0).unwrap().id, id);
+    manager.update_view(&area);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    // Some mesh is returned on every update until the rebuilt one replaces it
+    while manager.chunk_mesh(&0, &0).unwrap().id == id {
+        assert!(Instant::now() < deadline, "Chunk was never meshed again");
+        thread::sleep(Duration::from_millis(5));
+        manager.update_view(&area);
+    }
+    assert!(covers(manager.chunk_mesh(&0, &0).unwrap(), 4., 10.));
+}
+
+#[test]
+fn evicted_regions_keep_their_changes() {
+    let mut manager = flat_world("evict");
+    manager.set_region_budget(0);
+    manager.set_block(&4, &10, Block::STONE);
+    manager.update_view(&WorldRect::new(1024, 0, 1039, 15));
+    manager.clean().unwrap();
+
+    let stats = manager.region_stats();
+    assert_eq!(stats.evictions, 1);
+    assert_eq!(manager.peek_block(&4, &10), None);
+
+    // Loading it again waits for the background save to finish
+    assert_eq!(manager.get_block(&4, &10), Block::STONE);
+    assert!(manager.region_stats().misses > stats.misses);
+}
+
+/// Whether the mesh has a quad over the center of a block of its chunk
+fn covers(mesh: &ChunkMesh, x: f32, y: f32) -> bool {
+    mesh.mesh
+        .iter()
+        .any(|quad| quad.rect.contains(vec2(x + 0.5, y + 0.5)))
+}
+
+#[test]
+fn outdated_meshes_are_replaced() {
+    let mut manager = flat_world("outdated");
+    let area = WorldRect::new(0, 0, 15, 15);
+    wait_for_view(&mut manager, &area);
+
+    // Each change lands while the mesh of the one before may still be built,
+    // the result of that request is outdated on arrival
+    for x in 4..12 {
+        manager.set_block(&x, &10, Block::STONE);
+        manager.update_view(&area);
+    }
+    wait_for_view(&mut manager, &area);
+    let mesh = manager.chunk_mesh(&0, &0).unwrap();
+    assert!((4..12).all(|x| covers(mesh, x as f32, 10.)));
+    assert!(!covers(mesh, 12., 10.));
+}
+
+#[test]
+fn region_lookups_are_counted_once_per_frame() {
+    let mut manager = flat_world("stats");
+    let area = WorldRect::new(16, 16, 63, 63);
+    manager.update_view(&area);
+    manager.update_view(&area);
+    // Still loading, asking again does not start another load
+    assert_eq!(manager.region_stats().misses, 1);
+
+    wait_for_view(&mut manager, &area);
+    let hits = manager.region_stats().hits;
+    manager.update_view(&area);
+    // Nine chunks and their neighbours all live in the same region
+    assert_eq!(manager.region_stats().hits, hits + 1);
+    assert_eq!(manager.region_stats().misses, 1);
+}